@@ -0,0 +1,202 @@
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+
+use linalg::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	canvas::{BackendKind, CanvasWidget},
+	headless::HeadlessRenderer,
+};
+
+/// Board sizes measured by default, in strokes
+pub const DEFAULT_BOARD_SIZES: [usize; 4] = [100, 1_000, 10_000, 100_000];
+const EVENTS_PER_STROKE: usize = 32;
+/// Page area per stroke, so boards of every size are equally dense
+const AREA_PER_STROKE: f64 = 128.0 * 128.0;
+const VIEW_SIZE: (u32, u32) = (1024, 768);
+/// Each measurement is repeated until it took this long, within the iteration limits
+const TARGET_TIME: Duration = Duration::from_secs(1);
+const MIN_ITERATIONS: usize = 3;
+const MAX_ITERATIONS: usize = 200;
+
+#[derive(Debug, Default)]
+pub struct BenchOptions {
+	pub board_sizes: Vec<usize>,
+	/// Write the report as JSON to this file
+	pub output: Option<PathBuf>,
+	/// Compare against a report written by an earlier run
+	pub baseline: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Measurement {
+	pub name: String,
+	pub strokes: usize,
+	pub iterations: usize,
+	pub median_ms: f64,
+	pub min_ms: f64,
+	pub max_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchReport {
+	/// The graphics adapter the render measurements ran on
+	pub adapter: String,
+	pub backend: String,
+	pub measurements: Vec<Measurement>,
+}
+
+/// Deterministic xorshift generator, so every run draws the same boards
+struct Rng(u64);
+
+impl Rng {
+	fn next_f64(&mut self) -> f64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		(self.0 >> 11) as f64 / (1u64 << 53) as f64
+	}
+}
+
+/// Make a random walk starting somewhere in a square board of the given side length
+fn random_stroke(rng: &mut Rng, side: f64) -> Vec<(Point2, f32)> {
+	let mut pos = Point2::new(rng.next_f64() * side, rng.next_f64() * side);
+	let mut angle = rng.next_f64() * std::f64::consts::TAU;
+	(0..EVENTS_PER_STROKE)
+		.map(|i| {
+			angle += (rng.next_f64() - 0.5) * 0.8;
+			pos += Vec2::new(angle.cos(), angle.sin()) * 4.0;
+			let pressure = 0.5 + 0.5 * (i as f32 / EVENTS_PER_STROKE as f32 * std::f32::consts::PI).sin();
+			(pos, pressure)
+		})
+		.collect()
+}
+
+fn draw_stroke(widget: &mut CanvasWidget, stroke: &[(Point2, f32)]) {
+	widget.canvas.start_stroke();
+	for &(pos, pressure) in stroke {
+		widget.canvas.move_stroke(pos, pressure);
+	}
+	widget.canvas.end_stroke();
+}
+
+/// Time `work` repeatedly, after one untimed warmup run
+fn measure(name: &str, strokes: usize, mut work: impl FnMut()) -> Measurement {
+	work();
+
+	let mut times = Vec::new();
+	let start = Instant::now();
+	while times.len() < MIN_ITERATIONS || (times.len() < MAX_ITERATIONS && start.elapsed() < TARGET_TIME) {
+		let iteration = Instant::now();
+		work();
+		times.push(iteration.elapsed().as_secs_f64() * 1000.0);
+	}
+	times.sort_by(f64::total_cmp);
+
+	Measurement {
+		name: name.to_string(),
+		strokes,
+		iterations: times.len(),
+		median_ms: times[times.len() / 2],
+		min_ms: times[0],
+		max_ms: times[times.len() - 1],
+	}
+}
+
+fn bench_board(renderer: &mut HeadlessRenderer, strokes: usize) -> Vec<Measurement> {
+	let mut rng = Rng(0x5eed_0000 + strokes as u64);
+	let side = (strokes as f64 * AREA_PER_STROKE).sqrt();
+	let mut widget = CanvasWidget::new(VIEW_SIZE.0, VIEW_SIZE.1);
+	for _ in 0..strokes {
+		draw_stroke(&mut widget, &random_stroke(&mut rng, side));
+	}
+
+	let mut measurements = Vec::new();
+
+	// Adding one more stroke, undone afterwards so the board keeps its size
+	let stroke = random_stroke(&mut rng, side);
+	measurements.push(measure("ingest stroke", strokes, || {
+		draw_stroke(&mut widget, &stroke);
+		widget.canvas.undo();
+	}));
+
+	// A window-sized part of the board at full detail, and the whole board zoomed out
+	let center = Point2::new(side * 0.5, side * 0.5);
+	widget.pan = center - widget.center();
+	let detail = (widget.pan, widget.zoom);
+	widget.fit_to_content(0.0);
+	let overview = (widget.pan, widget.zoom);
+
+	for (view, (pan, zoom)) in [("detail", detail), ("overview", overview)] {
+		widget.pan = pan;
+		widget.zoom = zoom;
+		measurements.push(measure(&format!("encode scene ({view})"), strokes, || {
+			std::hint::black_box(widget.scene());
+		}));
+		measurements.push(measure(&format!("render ({view})"), strokes, || {
+			renderer.render_to_texture(&widget).unwrap()
+		}));
+	}
+
+	measurements
+}
+
+fn print_report(report: &BenchReport, baseline: Option<&BenchReport>) {
+	let baseline: HashMap<(&str, usize), f64> = baseline
+		.map(|baseline| {
+			baseline
+				.measurements
+				.iter()
+				.map(|m| ((m.name.as_str(), m.strokes), m.median_ms))
+				.collect()
+		})
+		.unwrap_or_default();
+
+	println!("Adapter: {}, backend: {}", report.adapter, report.backend);
+	println!(
+		"{:<24} {:>8} {:>12} {:>12} {:>12} {:>8}",
+		"measurement", "strokes", "median ms", "min ms", "max ms", "change"
+	);
+	for m in &report.measurements {
+		let change = match baseline.get(&(m.name.as_str(), m.strokes)) {
+			Some(&old) => format!("{:+.1}%", (m.median_ms / old - 1.0) * 100.0),
+			None => String::new(),
+		};
+		println!(
+			"{:<24} {:>8} {:>12.3} {:>12.3} {:>12.3} {:>8}",
+			m.name, m.strokes, m.median_ms, m.min_ms, m.max_ms, change
+		);
+	}
+}
+
+fn load_report(path: &Path) -> anyhow::Result<BenchReport> {
+	let data = std::fs::read_to_string(path)?;
+	serde_json::from_str(&data).map_err(|e| anyhow::format_err!("Invalid benchmark report '{}': {e}", path.display()))
+}
+
+/// Measure stroke ingestion, scene encoding and rendering on boards of each size and print a report
+pub fn run(options: &BenchOptions, backend: BackendKind) -> anyhow::Result<()> {
+	let baseline = options.baseline.as_deref().map(load_report).transpose()?;
+	let mut renderer = HeadlessRenderer::new(backend)?;
+	let info = &renderer.graphics.adapter_info;
+	let mut report = BenchReport {
+		adapter: format!("{} ({:?})", info.name, info.backend),
+		backend: backend.name().to_string(),
+		measurements: Vec::new(),
+	};
+
+	for &strokes in &options.board_sizes {
+		log::info!("Benchmarking a board of {strokes} strokes");
+		report.measurements.extend(bench_board(&mut renderer, strokes));
+	}
+
+	print_report(&report, baseline.as_ref());
+	if let Some(path) = &options.output {
+		std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+	}
+	Ok(())
+}
@@ -2,35 +2,35 @@ use wgpu::{Device, RenderPipeline, TextureFormat, TextureView};
 
 use crate::Graphics;
 
+/// Check whether a render target takes linear colors. sRGB formats encode them on write, and float formats are
+/// handed to the compositor as linear values.
+pub fn is_linear_target(format: TextureFormat) -> bool {
+	format.describe().srgb || matches!(format, TextureFormat::Rgba16Float | TextureFormat::Rgba32Float)
+}
+
+/// Copies the canvas texture to a target of the same size, such as the window surface. The canvas holds
+/// sRGB-encoded colors, which are decoded for targets that take linear ones, so every color reaches the screen
+/// exactly as it was drawn.
 pub struct BlitPipeline {
 	pipeline: RenderPipeline,
-	sampler: wgpu::Sampler,
 	bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl BlitPipeline {
 	pub fn new(graphics: &Graphics, target_format: TextureFormat) -> Self {
-		let shader = crate::util::load_wgsl_shader(&graphics.device, "blit.wgsl");
+		let shader = crate::shader::load(&graphics.device, "blit.wgsl");
 		let bind_group_layout = graphics.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
 			label: Some("Blit Bind Group Layout"),
-			entries: &[
-				wgpu::BindGroupLayoutEntry {
-					binding: 0,
-					visibility: wgpu::ShaderStages::FRAGMENT,
-					ty: wgpu::BindingType::Texture {
-						sample_type: wgpu::TextureSampleType::Float { filterable: true },
-						view_dimension: wgpu::TextureViewDimension::D2,
-						multisampled: false,
-					},
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 1,
-					visibility: wgpu::ShaderStages::FRAGMENT,
-					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-					count: None,
+			entries: &[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Float { filterable: false },
+					view_dimension: wgpu::TextureViewDimension::D2,
+					multisampled: false,
 				},
-			],
+				count: None,
+			}],
 		});
 
 		let pipeline_layout = graphics.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -48,7 +48,11 @@ impl BlitPipeline {
 			},
 			fragment: Some(wgpu::FragmentState {
 				module: &shader,
-				entry_point: "fs_main",
+				entry_point: if is_linear_target(target_format) {
+					"fs_linear"
+				} else {
+					"fs_encoded"
+				},
 				targets: &[Some(wgpu::ColorTargetState {
 					format: target_format,
 					blend: Some(wgpu::BlendState {
@@ -76,52 +80,25 @@ impl BlitPipeline {
 			multiview: None,
 		});
 
-		let sampler = graphics.device.create_sampler(&wgpu::SamplerDescriptor {
-			label: Some("Blit Sampler"),
-			address_mode_u: wgpu::AddressMode::ClampToEdge,
-			address_mode_v: wgpu::AddressMode::ClampToEdge,
-			address_mode_w: wgpu::AddressMode::ClampToEdge,
-			mag_filter: wgpu::FilterMode::Linear,
-			min_filter: wgpu::FilterMode::Linear,
-			mipmap_filter: wgpu::FilterMode::Linear,
-			lod_min_clamp: 1.0,
-			lod_max_clamp: 1.0,
-			compare: None,
-			anisotropy_clamp: None,
-			border_color: None,
-		});
-
 		Self {
 			pipeline,
-			sampler,
 			bind_group_layout,
 		}
 	}
 
-	fn create_bind_group(
-		device: &Device,
-		layout: &wgpu::BindGroupLayout,
-		view: &wgpu::TextureView,
-		sampler: &wgpu::Sampler,
-	) -> wgpu::BindGroup {
+	fn create_bind_group(device: &Device, layout: &wgpu::BindGroupLayout, view: &wgpu::TextureView) -> wgpu::BindGroup {
 		device.create_bind_group(&wgpu::BindGroupDescriptor {
 			label: Some("Blit Bind Group"),
 			layout,
-			entries: &[
-				wgpu::BindGroupEntry {
-					binding: 0,
-					resource: wgpu::BindingResource::TextureView(view),
-				},
-				wgpu::BindGroupEntry {
-					binding: 1,
-					resource: wgpu::BindingResource::Sampler(sampler),
-				},
-			],
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::TextureView(view),
+			}],
 		})
 	}
 
 	pub fn blit(&self, graphics: &Graphics, source_view: &TextureView, target_view: &TextureView) {
-		let bind_group = Self::create_bind_group(&graphics.device, &self.bind_group_layout, &source_view, &self.sampler);
+		let bind_group = Self::create_bind_group(&graphics.device, &self.bind_group_layout, source_view);
 		let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
 			label: Some("Blit Command Encoder"),
 		});
@@ -150,3 +127,72 @@ impl BlitPipeline {
 		graphics.queue.submit([commands]);
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{canvas::OutputTexture, headless::read_texture};
+
+	/// Colors picked in the app must show up on screen with exactly the values they were picked with, whatever
+	/// format the surface has
+	#[test]
+	fn colors_round_trip_through_the_blit() {
+		let graphics = match Graphics::headless() {
+			Ok(graphics) => graphics,
+			Err(e) => {
+				eprintln!("Skipping the blit test, there is no graphics adapter: {e}");
+				return;
+			}
+		};
+
+		// Every 8-bit value in every channel
+		let (width, height) = (16, 16);
+		let pixels: Vec<u8> = (0..=255u8).flat_map(|i| [i, 255 - i, i.wrapping_mul(7), 255]).collect();
+		let source = OutputTexture::new(&graphics, width, height);
+		graphics.queue.write_texture(
+			source.get_texture().as_image_copy(),
+			&pixels,
+			wgpu::ImageDataLayout {
+				offset: 0,
+				bytes_per_row: std::num::NonZeroU32::new(width * 4),
+				rows_per_image: None,
+			},
+			wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+		);
+
+		for format in [
+			TextureFormat::Rgba8Unorm,
+			TextureFormat::Rgba8UnormSrgb,
+			TextureFormat::Bgra8Unorm,
+			TextureFormat::Bgra8UnormSrgb,
+		] {
+			let target = graphics.device.create_texture(&wgpu::TextureDescriptor {
+				label: Some("Blit Test Target"),
+				size: wgpu::Extent3d {
+					width,
+					height,
+					depth_or_array_layers: 1,
+				},
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				format,
+				usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+			});
+			let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+			BlitPipeline::new(&graphics, format).blit(&graphics, source.get_texture_view(), &view);
+
+			let mut actual = read_texture(&graphics, &target, width, height).unwrap().pixels;
+			if matches!(format, TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb) {
+				for pixel in actual.chunks_mut(4) {
+					pixel.swap(0, 2);
+				}
+			}
+			assert!(actual == pixels, "Colors changed when blitting to {format:?}");
+		}
+	}
+}
@@ -1,3 +1,26 @@
+mod backend;
+mod background;
+mod chunk;
+#[cfg(feature = "backend-forma")]
+mod forma_canvas;
+mod lod;
+#[cfg(feature = "backend-forma")]
+mod outline;
+#[cfg(feature = "backend-raster")]
+mod raster_canvas;
+#[cfg(feature = "backend-tri")]
+mod tri_canvas;
 mod vello_canvas;
 
+pub use self::backend::*;
+pub use self::background::{Background, Marks, Pattern};
+#[cfg(feature = "backend-forma")]
+pub use self::forma_canvas::FormaBackend;
+#[cfg(feature = "backend-raster")]
+pub use self::raster_canvas::{RasterBackend, RasterCanvas};
+#[cfg(feature = "backend-tri")]
+pub use self::tri_canvas::TriBackend;
 pub use self::vello_canvas::*;
+
+#[cfg(test)]
+mod golden;
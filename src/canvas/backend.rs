@@ -0,0 +1,486 @@
+use serde::{de::IntoDeserializer, Deserialize};
+use wgpu::{Texture, TextureView};
+
+use linalg::prelude::*;
+
+use super::{Background, Canvas, CanvasWidget, Layer, StrokeChange};
+use crate::Graphics;
+
+/// The renderers a canvas can be drawn with. Each one is only available if its `backend-*` cargo feature is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+	Vello,
+	Forma,
+	/// Strokes tessellated into triangles with lyon
+	Tri,
+	/// Strokes rasterized on the CPU and uploaded to a texture
+	Raster,
+}
+
+impl BackendKind {
+	pub const ALL: [BackendKind; 4] = [BackendKind::Vello, BackendKind::Forma, BackendKind::Tri, BackendKind::Raster];
+
+	/// Parse a backend name as used in the config, such as `"vello"`
+	pub fn parse(name: &str) -> anyhow::Result<Self> {
+		Self::deserialize(name.into_deserializer())
+			.map_err(|_: serde::de::value::Error| anyhow::format_err!("Unknown rendering backend '{name}'"))
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			BackendKind::Vello => "vello",
+			BackendKind::Forma => "forma",
+			BackendKind::Tri => "tri",
+			BackendKind::Raster => "raster",
+		}
+	}
+
+	/// Check whether this backend was compiled in
+	pub fn is_available(self) -> bool {
+		match self {
+			BackendKind::Vello => cfg!(feature = "backend-vello"),
+			BackendKind::Forma => cfg!(feature = "backend-forma"),
+			BackendKind::Tri => cfg!(feature = "backend-tri"),
+			BackendKind::Raster => cfg!(feature = "backend-raster"),
+		}
+	}
+
+	pub fn available() -> impl Iterator<Item = BackendKind> {
+		Self::ALL.into_iter().filter(|kind| kind.is_available())
+	}
+
+	/// Get the backend used when none is configured
+	pub fn preferred() -> anyhow::Result<Self> {
+		Self::available()
+			.next()
+			.ok_or(anyhow::format_err!("No rendering backend was compiled in"))
+	}
+}
+
+/// Follows the strokes of a canvas as they change, so a renderer can update what it derived from each stroke instead
+/// of starting over on every render. Indices are into `Canvas::layers` at the time of the call, and every call
+/// leaves the strokes the hooks know about matching those layers. `StrokeSync` works out which hooks to call.
+pub trait StrokeHooks {
+	/// Forget all strokes and take these instead
+	fn reset_strokes(&mut self, layers: &[Layer]);
+
+	fn insert_stroke(&mut self, index: usize, layer: &Layer);
+
+	fn remove_stroke(&mut self, index: usize);
+
+	/// The stroke at `index` changed in some way other than gaining samples, e.g. it was moved
+	fn replace_stroke(&mut self, index: usize, layer: &Layer);
+
+	/// A stroke was started on top of the others. It may already have samples.
+	fn start_stroke(&mut self, index: usize, layer: &Layer) {
+		self.insert_stroke(index, layer);
+	}
+
+	/// The stroke in progress at `index` gained samples at the end
+	fn push_stroke(&mut self, index: usize, layer: &Layer) {
+		self.replace_stroke(index, layer);
+	}
+
+	/// The stroke in progress at `index` was finished, possibly gaining samples since the last call
+	fn end_stroke(&mut self, index: usize, layer: &Layer) {
+		self.replace_stroke(index, layer);
+	}
+}
+
+/// Draws the view of a `CanvasWidget` into an `OutputTexture`. Changes to the strokes arrive through the
+/// `StrokeHooks` before each render, so the whole stroke lifecycle, undo and erasing included, shows up the same on
+/// every backend.
+pub trait CanvasBackend: StrokeHooks {
+	fn kind(&self) -> BackendKind;
+
+	fn resize(&mut self, graphics: &Graphics, width: u32, height: u32);
+
+	fn render(&mut self, graphics: &Graphics, widget: &CanvasWidget) -> anyhow::Result<()>;
+
+	fn output(&self) -> &OutputTexture;
+
+	/// Rebuild the pipelines from the current shader sources after they changed on disk, keeping the old ones if the
+	/// new shaders don't compile. Backends without WGSL shaders of their own have nothing to do.
+	fn reload_shaders(&mut self, _graphics: &Graphics) {}
+}
+
+/// How a stroke the hooks already know about changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Update {
+	Pushed,
+	Ended,
+	Replaced,
+}
+
+/// A stroke of the canvas while `StrokeSync` works out what happened to it
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+	/// A stroke the hooks know about, at `index` in what they know
+	Known { index: usize, update: Option<Update> },
+	/// A stroke the hooks don't know about yet
+	New { in_progress: bool },
+}
+
+/// Keeps `StrokeHooks` up to date with a canvas. Changes made since the last sync are combined, so a stroke that was
+/// drawn and then erased before a render never reaches the hooks, and a stroke gaining many samples is only passed on
+/// once.
+#[derive(Debug, Default)]
+pub struct StrokeSync {
+	/// `Canvas::id` of the canvas followed so far
+	canvas: Option<u64>,
+	/// Number of changes to the canvas already passed on
+	seen: u64,
+	/// Number of strokes the hooks know about
+	count: usize,
+}
+
+impl StrokeSync {
+	/// Pass the changes to the strokes of `canvas` since the last call on to `hooks`, or all of its strokes if this
+	/// is a different canvas or it no longer remembers the changes
+	pub fn apply<H: StrokeHooks + ?Sized>(&mut self, canvas: &Canvas, hooks: &mut H) {
+		let layers = canvas.layers();
+		if self.canvas == Some(canvas.id()) && self.apply_changes(canvas, hooks) {
+			return;
+		}
+
+		hooks.reset_strokes(layers);
+		self.canvas = Some(canvas.id());
+		self.seen = canvas.change_count();
+		self.count = layers.len();
+	}
+
+	/// Pass on the changes since the last call, returning whether that was possible
+	fn apply_changes<H: StrokeHooks + ?Sized>(&mut self, canvas: &Canvas, hooks: &mut H) -> bool {
+		let changes = match canvas.changes_since(self.seen) {
+			Some(changes) => changes,
+			None => return false,
+		};
+
+		let mut slots: Vec<Slot> = (0..self.count).map(|index| Slot::Known { index, update: None }).collect();
+		let mut removed = Vec::new();
+		for change in changes {
+			match change {
+				StrokeChange::Started(i) | StrokeChange::Inserted(i) if i <= slots.len() => {
+					let in_progress = matches!(change, StrokeChange::Started(_));
+					slots.insert(i, Slot::New { in_progress });
+				}
+				StrokeChange::Removed(i) if i < slots.len() => {
+					if let Slot::Known { index, .. } = slots.remove(i) {
+						removed.push(index);
+					}
+				}
+				StrokeChange::Pushed(i) | StrokeChange::Ended(i) | StrokeChange::Replaced(i) if i < slots.len() => {
+					let update = match change {
+						StrokeChange::Pushed(_) => Update::Pushed,
+						StrokeChange::Ended(_) => Update::Ended,
+						_ => Update::Replaced,
+					};
+					match &mut slots[i] {
+						Slot::Known { update: known, .. } => *known = (*known).max(Some(update)),
+						// New strokes are passed on whole, so only whether they are still in progress matters
+						Slot::New { in_progress } => {
+							if update == Update::Ended {
+								*in_progress = false;
+							}
+						}
+					}
+				}
+				_ => return false,
+			}
+		}
+
+		let layers = canvas.layers();
+		if slots.len() != layers.len() {
+			log::error!("Lost track of the strokes of the canvas, passing them all on again");
+			return false;
+		}
+
+		// Removing from the back first keeps the indices of the strokes still to be removed valid, and afterwards the
+		// remaining strokes are in their final order, so the new ones can be inserted from the front
+		removed.sort_unstable();
+		for &index in removed.iter().rev() {
+			hooks.remove_stroke(index);
+		}
+		for (i, (slot, layer)) in slots.into_iter().zip(layers).enumerate() {
+			match slot {
+				Slot::Known { update, .. } => match update {
+					None => {}
+					Some(Update::Pushed) => hooks.push_stroke(i, layer),
+					Some(Update::Ended) => hooks.end_stroke(i, layer),
+					Some(Update::Replaced) => hooks.replace_stroke(i, layer),
+				},
+				Slot::New { in_progress: true } => hooks.start_stroke(i, layer),
+				Slot::New { in_progress: false } => hooks.insert_stroke(i, layer),
+			}
+		}
+
+		self.seen = canvas.change_count();
+		self.count = layers.len();
+		true
+	}
+}
+
+/// Everything besides the strokes that the pixels of a view depend on, for backends drawing in widget coordinates to
+/// tell when they have to draw everything again
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewState {
+	pan: Vec2,
+	zoom: f64,
+	rotation: f64,
+	scale_factor: f64,
+	size: (u32, u32),
+	background: Background,
+}
+
+impl ViewState {
+	pub fn new(widget: &CanvasWidget) -> Self {
+		Self {
+			pan: widget.pan,
+			zoom: widget.zoom,
+			rotation: widget.rotation,
+			scale_factor: widget.scale_factor(),
+			size: (widget.get_width(), widget.get_height()),
+			background: widget.background.clone(),
+		}
+	}
+}
+
+/// Create a backend of the given kind
+pub fn create_backend(kind: BackendKind, graphics: &Graphics, width: u32, height: u32) -> anyhow::Result<Box<dyn CanvasBackend>> {
+	log::info!("Rendering with the {} backend", kind.name());
+	Ok(match kind {
+		#[cfg(feature = "backend-vello")]
+		BackendKind::Vello => Box::new(super::VelloBackend::new(graphics, width, height)?),
+		#[cfg(feature = "backend-forma")]
+		BackendKind::Forma => Box::new(super::FormaBackend::new(graphics, width, height)),
+		#[cfg(feature = "backend-tri")]
+		BackendKind::Tri => Box::new(super::TriBackend::new(graphics, width, height)),
+		#[cfg(feature = "backend-raster")]
+		BackendKind::Raster => Box::new(super::RasterBackend::new(graphics, width, height)),
+		#[allow(unreachable_patterns)]
+		_ => {
+			return Err(anyhow::format_err!(
+				"The {0} backend isn't compiled in, enable the backend-{0} feature",
+				kind.name()
+			))
+		}
+	})
+}
+
+/// The texture a backend renders into, which the blitter can show and which can be read back
+pub struct OutputTexture {
+	width: u32,
+	height: u32,
+	texture: Texture,
+	view: TextureView,
+}
+
+impl OutputTexture {
+	pub fn new(graphics: &Graphics, width: u32, height: u32) -> Self {
+		let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Canvas Output Texture"),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: wgpu::TextureFormat::Rgba8Unorm,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+				| wgpu::TextureUsages::COPY_SRC
+				| wgpu::TextureUsages::COPY_DST
+				| wgpu::TextureUsages::STORAGE_BINDING
+				| wgpu::TextureUsages::TEXTURE_BINDING,
+		});
+		let view = texture.create_view(&wgpu::TextureViewDescriptor {
+			label: Some("Canvas Output TextureView"),
+			format: Some(wgpu::TextureFormat::Rgba8Unorm),
+			dimension: Some(wgpu::TextureViewDimension::D2),
+			aspect: wgpu::TextureAspect::All,
+			base_mip_level: 0,
+			mip_level_count: None,
+			base_array_layer: 0,
+			array_layer_count: None,
+		});
+
+		Self {
+			width,
+			height,
+			texture,
+			view,
+		}
+	}
+
+	pub fn get_texture(&self) -> &Texture {
+		&self.texture
+	}
+
+	pub fn get_texture_view(&self) -> &TextureView {
+		&self.view
+	}
+
+	pub fn get_width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn get_height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Keeps the origin and sample count of every stroke it is told about, and the names of the hooks called
+	#[derive(Default)]
+	struct Mirror {
+		strokes: Vec<(Vec2, usize)>,
+		calls: Vec<&'static str>,
+	}
+
+	fn summary(layer: &Layer) -> (Vec2, usize) {
+		(layer.origin(), layer.events().len())
+	}
+
+	impl StrokeHooks for Mirror {
+		fn reset_strokes(&mut self, layers: &[Layer]) {
+			self.strokes = layers.iter().map(summary).collect();
+			self.calls.push("reset");
+		}
+
+		fn insert_stroke(&mut self, index: usize, layer: &Layer) {
+			self.strokes.insert(index, summary(layer));
+			self.calls.push("insert");
+		}
+
+		fn remove_stroke(&mut self, index: usize) {
+			self.strokes.remove(index);
+			self.calls.push("remove");
+		}
+
+		fn replace_stroke(&mut self, index: usize, layer: &Layer) {
+			self.strokes[index] = summary(layer);
+			self.calls.push("replace");
+		}
+
+		fn start_stroke(&mut self, index: usize, layer: &Layer) {
+			self.strokes.insert(index, summary(layer));
+			self.calls.push("start");
+		}
+
+		fn push_stroke(&mut self, index: usize, layer: &Layer) {
+			self.strokes[index] = summary(layer);
+			self.calls.push("push");
+		}
+
+		fn end_stroke(&mut self, index: usize, layer: &Layer) {
+			self.strokes[index] = summary(layer);
+			self.calls.push("end");
+		}
+	}
+
+	/// Apply the changes to the mirror, check it matches the canvas and return the hooks called
+	fn sync(sync: &mut StrokeSync, canvas: &Canvas, mirror: &mut Mirror) -> Vec<&'static str> {
+		sync.apply(canvas, mirror);
+		assert_eq!(mirror.strokes, canvas.layers().iter().map(summary).collect::<Vec<_>>());
+		std::mem::take(&mut mirror.calls)
+	}
+
+	/// Draw a horizontal stroke of `samples` samples
+	fn draw(canvas: &mut Canvas, x: f64, y: f64, samples: usize) {
+		canvas.start_stroke();
+		for i in 0..samples {
+			canvas.move_stroke(Point2::new(x + i as f64 * 10.0, y), 0.5);
+		}
+		canvas.end_stroke();
+	}
+
+	#[test]
+	fn hooks_follow_drawing_erasing_moving_and_undo() {
+		let mut canvas = Canvas::new();
+		let (mut strokes, mut mirror) = (StrokeSync::default(), Mirror::default());
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["reset"]);
+
+		for i in 0..4 {
+			draw(&mut canvas, 0.0, i as f64 * 100.0, 5);
+		}
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["insert"; 4]);
+
+		assert!(canvas.erase_at(Point2::new(20.0, 100.0), 2.0));
+		assert!(canvas.erase_at(Point2::new(20.0, 200.0), 2.0));
+		canvas.commit_erase();
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["remove", "remove"]);
+
+		assert!(canvas.undo());
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["insert", "insert"]);
+
+		let around = [(-10.0, 90.0), (100.0, 90.0), (100.0, 210.0), (-10.0, 210.0)].map(|(x, y)| Point2::new(x, y));
+		assert!(canvas.select_in_polygon(&around));
+		canvas.move_selection(Vec2::new(5000.0, 0.0));
+		canvas.move_selection(Vec2::new(5000.0, 0.0));
+		canvas.commit_move();
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["replace", "replace"]);
+
+		assert!(canvas.undo());
+		assert!(canvas.undo());
+		assert!(canvas.redo());
+		sync(&mut strokes, &canvas, &mut mirror);
+	}
+
+	#[test]
+	fn changes_between_syncs_are_combined() {
+		let mut canvas = Canvas::new();
+		let (mut strokes, mut mirror) = (StrokeSync::default(), Mirror::default());
+		draw(&mut canvas, 0.0, 0.0, 5);
+		sync(&mut strokes, &canvas, &mut mirror);
+
+		canvas.start_stroke();
+		canvas.move_stroke(Point2::new(0.0, 50.0), 0.5);
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["start"]);
+		for i in 1..20 {
+			canvas.move_stroke(Point2::new(i as f64, 50.0), 0.5);
+		}
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["push"]);
+		canvas.move_stroke(Point2::new(30.0, 50.0), 0.5);
+		canvas.end_stroke();
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["end"]);
+
+		// A stroke drawn and erased again, or one cancelled, never reaches the hooks
+		draw(&mut canvas, 0.0, 100.0, 5);
+		assert!(canvas.erase_at(Point2::new(20.0, 100.0), 2.0));
+		canvas.commit_erase();
+		canvas.start_stroke();
+		canvas.move_stroke(Point2::new(0.0, 300.0), 0.5);
+		canvas.cancel_stroke();
+		assert!(sync(&mut strokes, &canvas, &mut mirror).is_empty());
+
+		// Erasing a stroke below the one being drawn
+		canvas.start_stroke();
+		canvas.move_stroke(Point2::new(0.0, 400.0), 0.5);
+		assert!(canvas.erase_at(Point2::new(20.0, 0.0), 2.0));
+		canvas.move_stroke(Point2::new(10.0, 400.0), 0.5);
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["remove", "start"]);
+	}
+
+	#[test]
+	fn hooks_start_over_for_another_canvas_or_forgotten_changes() {
+		let mut canvas = Canvas::new();
+		let (mut strokes, mut mirror) = (StrokeSync::default(), Mirror::default());
+		draw(&mut canvas, 0.0, 0.0, 5);
+		assert_eq!(sync(&mut strokes, &canvas, &mut mirror), ["reset"]);
+
+		let mut other = Canvas::new();
+		draw(&mut other, 0.0, 0.0, 3);
+		assert_eq!(sync(&mut strokes, &other, &mut mirror), ["reset"]);
+
+		other.start_stroke();
+		for i in 0..5000 {
+			other.move_stroke(Point2::new(i as f64, 0.0), 0.5);
+		}
+		assert_eq!(sync(&mut strokes, &other, &mut mirror), ["reset"]);
+	}
+}
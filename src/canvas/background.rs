@@ -0,0 +1,352 @@
+use linalg::prelude::*;
+use serde::Deserialize;
+use vello::peniko::Color;
+
+use super::CanvasWidget;
+
+/// Width in logical pixels of the lines of a pattern
+const LINE_WIDTH: f64 = 1.0;
+/// Radius in logical pixels of the dots of a pattern
+const DOT_RADIUS: f64 = 1.5;
+/// Marks closer than this many logical pixels are left out, so zooming out never fills the view with them
+const MIN_SPACING: f64 = 6.0;
+/// Height of an equilateral triangle with sides of length 1
+const TRIANGLE_HEIGHT: f64 = 0.866_025_403_784_438_6;
+
+/// What is drawn on the page under the strokes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+	Plain,
+	/// Square grid
+	Grid,
+	/// A dot at every intersection of the square grid
+	Dots,
+	/// Horizontal lines
+	Ruled,
+	/// Grid of equilateral triangles, with vertical lines and lines at 30° either side of horizontal
+	Isometric,
+}
+
+impl Pattern {
+	pub const ALL: [Pattern; 5] = [
+		Pattern::Plain,
+		Pattern::Grid,
+		Pattern::Dots,
+		Pattern::Ruled,
+		Pattern::Isometric,
+	];
+
+	/// Get the pattern after this one in `ALL`, wrapping around
+	pub fn next(self) -> Self {
+		let i = Self::ALL.iter().position(|&pattern| pattern == self).unwrap_or(0);
+		Self::ALL[(i + 1) % Self::ALL.len()]
+	}
+
+	/// Get the directions in radians of the families of lines making up the pattern, and the distance between the
+	/// lines of a family relative to the spacing of the pattern
+	fn families(self) -> &'static [(f64, f64)] {
+		use std::f64::consts::FRAC_PI_2;
+		const THIRTY_DEGREES: f64 = FRAC_PI_2 / 3.0;
+		match self {
+			Pattern::Plain | Pattern::Dots => &[],
+			Pattern::Grid => &[(0.0, 1.0), (FRAC_PI_2, 1.0)],
+			Pattern::Ruled => &[(0.0, 1.0)],
+			Pattern::Isometric => &[
+				(FRAC_PI_2, TRIANGLE_HEIGHT),
+				(THIRTY_DEGREES, TRIANGLE_HEIGHT),
+				(-THIRTY_DEGREES, TRIANGLE_HEIGHT),
+			],
+		}
+	}
+}
+
+/// The pattern of the page, read from the `[background]` section of the config.
+///
+/// Patterns are generated for the part of the page in view on every render, so they reach as far as the page does.
+/// At the zoom the view starts at, lines are `spacing` apart. Zooming in fades in lines halfway between them, so at
+/// twice the zoom the pattern looks like it did before, and zooming out fades every other line out the same way.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Background {
+	pub pattern: Pattern,
+	/// Distance in page units between lines, or dots, of the pattern at the default zoom
+	pub spacing: f64,
+	/// RGB color of the lines and dots
+	pub color: [u8; 3],
+}
+
+impl Default for Background {
+	fn default() -> Self {
+		Self {
+			pattern: Pattern::Plain,
+			spacing: 32.0,
+			color: [0xc0, 0xcc, 0xdc],
+		}
+	}
+}
+
+/// Lines and dots of one color, in widget coordinates
+pub struct Marks {
+	pub color: Color,
+	pub lines: Vec<(Point2, Point2)>,
+	pub line_width: f64,
+	pub dots: Vec<Point2>,
+	pub dot_radius: f64,
+}
+
+/// Which lines of the pattern are visible at some zoom
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Level {
+	/// Page distance between lines drawn in the full color
+	spacing: f64,
+	/// How far the lines halfway between them have faded in, from 0 to 1
+	fade: f64,
+}
+
+impl Background {
+	fn is_drawn(&self) -> bool {
+		self.pattern != Pattern::Plain && self.spacing > 0.0
+	}
+
+	fn level(&self, widget: &CanvasWidget) -> Level {
+		let min_spacing = MIN_SPACING * widget.scale_factor();
+		// Powers of two of the zoom see the same pattern, just at another level
+		let mut spacing = self.spacing * 2.0f64.powf(-widget.zoom.log2().floor());
+		while spacing * widget.pixel_scale() < min_spacing {
+			spacing *= 2.0;
+		}
+		let fade = spacing * widget.zoom / self.spacing - 1.0;
+		let fade = if spacing * 0.5 * widget.pixel_scale() < min_spacing {
+			0.0
+		} else {
+			fade.clamp(0.0, 1.0)
+		};
+		Level { spacing, fade }
+	}
+
+	/// Get the page distance between the lines of the pattern that are clearly visible in the view, or `None` if
+	/// the pattern has no lines to speak of
+	pub fn visible_spacing(&self, widget: &CanvasWidget) -> Option<f64> {
+		if !self.is_drawn() {
+			return None;
+		}
+		let level = self.level(widget);
+		Some(if level.fade >= 0.5 {
+			level.spacing * 0.5
+		} else {
+			level.spacing
+		})
+	}
+
+	/// Get the intersection of the pattern nearest to a page point, or the nearest point on a line for ruled paper.
+	/// Only lines clearly visible at the zoom of the view count, see `visible_spacing`.
+	pub fn nearest_intersection(&self, widget: &CanvasWidget, point: Point2) -> Option<Point2> {
+		let spacing = self.visible_spacing(widget)?;
+		let round = |value: f64| (value / spacing).round() * spacing;
+		Some(match self.pattern {
+			Pattern::Plain => return None,
+			Pattern::Grid | Pattern::Dots => Point2::new(round(point.x), round(point.y)),
+			Pattern::Ruled => Point2::new(point.x, round(point.y)),
+			Pattern::Isometric => {
+				// Intersections lie on the vertical lines, `spacing` apart and shifted by half that on every other line
+				let column_spacing = spacing * TRIANGLE_HEIGHT;
+				let column = (point.x / column_spacing).floor();
+				[column, column + 1.0]
+					.into_iter()
+					.map(|column| {
+						let shift = if column.rem_euclid(2.0) == 1.0 { spacing * 0.5 } else { 0.0 };
+						Point2::new(column * column_spacing, round(point.y - shift) + shift)
+					})
+					.min_by(|a, b| (a - point).norm_squared().total_cmp(&(b - point).norm_squared()))?
+			}
+		})
+	}
+
+	/// Build the marks of the pattern covering the view: first the faded ones, then the ones in the full color
+	pub fn marks(&self, widget: &CanvasWidget) -> Vec<Marks> {
+		if !self.is_drawn() {
+			return Vec::new();
+		}
+
+		let level = self.level(widget);
+		let to_page = widget.transform();
+		let to_widget = widget.inv_transform();
+		let (width, height) = (widget.get_width() as f64, widget.get_height() as f64);
+		let corners = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)].map(|(x, y)| to_page * Point2::new(x, y));
+
+		let [r, g, b] = self.color;
+		let faded = |value: u8| (255.0 + (value as f64 - 255.0) * level.fade).round() as u8;
+		let new_marks = |color| Marks {
+			color,
+			lines: Vec::new(),
+			line_width: LINE_WIDTH * widget.scale_factor(),
+			dots: Vec::new(),
+			dot_radius: DOT_RADIUS * widget.scale_factor(),
+		};
+		let mut minor = new_marks(Color::rgb8(faded(r), faded(g), faded(b)));
+		let mut major = new_marks(Color::rgb8(r, g, b));
+		// Indices count lines of the finer level, so every other one belongs to the coarser level too
+		let (spacing, step) = if level.fade > 0.0 {
+			(level.spacing * 0.5, 2)
+		} else {
+			(level.spacing, 1)
+		};
+
+		for &(angle, distance) in self.pattern.families() {
+			for_each_line(&corners, angle, spacing * distance, |i, from, to| {
+				let marks = if i % step == 0 { &mut major } else { &mut minor };
+				marks.lines.push((to_widget * from, to_widget * to));
+			});
+		}
+
+		if self.pattern == Pattern::Dots {
+			let margin = major.dot_radius;
+			let x = range(&corners, Vec2::new(1.0, 0.0), spacing);
+			let y = range(&corners, Vec2::new(0.0, 1.0), spacing);
+			for i in x {
+				for j in y.clone() {
+					let dot = to_widget * Point2::new(i as f64 * spacing, j as f64 * spacing);
+					if dot.x < -margin || dot.y < -margin || dot.x > width + margin || dot.y > height + margin {
+						continue;
+					}
+					let marks = if i % step == 0 && j % step == 0 {
+						&mut major
+					} else {
+						&mut minor
+					};
+					marks.dots.push(dot);
+				}
+			}
+		}
+
+		[minor, major]
+			.into_iter()
+			.filter(|marks| !marks.lines.is_empty() || !marks.dots.is_empty())
+			.collect()
+	}
+}
+
+/// Get the indices of the multiples of `spacing` along `axis` that fall within a polygon
+fn range(polygon: &[Point2], axis: Vec2, spacing: f64) -> std::ops::RangeInclusive<i64> {
+	let (min, max) = polygon
+		.iter()
+		.map(|point| point.coords.dot(&axis))
+		.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+			(min.min(value), max.max(value))
+		});
+	(min / spacing).ceil() as i64..=(max / spacing).floor() as i64
+}
+
+/// Call `f` with the index and the ends of every line of a family crossing a polygon, clipped to its bounds along the
+/// lines. The lines run in the direction `angle` and are `spacing` apart, with line 0 through the page origin.
+fn for_each_line(polygon: &[Point2], angle: f64, spacing: f64, mut f: impl FnMut(i64, Point2, Point2)) {
+	let direction = Vec2::new(angle.cos(), angle.sin());
+	let normal = Vec2::new(-direction.y, direction.x);
+	let along = range(polygon, direction, 1.0);
+	let (start, end) = (*along.start() as f64 - 1.0, *along.end() as f64 + 1.0);
+	for i in range(polygon, normal, spacing) {
+		let offset = normal * (i as f64 * spacing);
+		f(
+			i,
+			Point2::from(offset + direction * start),
+			Point2::from(offset + direction * end),
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn widget(pattern: Pattern, zoom: f64) -> CanvasWidget {
+		let mut widget = CanvasWidget::new(320, 240);
+		widget.zoom = zoom;
+		widget.background.pattern = pattern;
+		widget
+	}
+
+	#[test]
+	fn grid_shows_the_configured_spacing_at_the_default_zoom() {
+		let widget = widget(Pattern::Grid, 1.0);
+		let marks = widget.background.marks(&widget);
+		assert_eq!(marks.len(), 1);
+		// Vertical lines from x = 0 to 320 and horizontal ones from y = 0 to 224
+		assert_eq!(marks[0].lines.len(), 11 + 8);
+		assert_eq!(widget.background.visible_spacing(&widget), Some(32.0));
+	}
+
+	#[test]
+	fn zooming_fades_in_lines_between_the_others() {
+		let halfway = widget(Pattern::Ruled, 1.5);
+		let level = halfway.background.level(&halfway);
+		assert_eq!(
+			level,
+			Level {
+				spacing: 32.0,
+				fade: 0.5
+			}
+		);
+		assert_eq!(halfway.background.visible_spacing(&halfway), Some(16.0));
+
+		// Just below and at twice the zoom, the pattern looks the same
+		let below = widget(Pattern::Ruled, 2.0 - 1e-9);
+		let at = widget(Pattern::Ruled, 2.0);
+		assert!((below.background.level(&below).fade - 1.0).abs() < 1e-6);
+		assert_eq!(
+			at.background.level(&at),
+			Level {
+				spacing: 16.0,
+				fade: 0.0
+			}
+		);
+	}
+
+	#[test]
+	fn marks_cover_the_view_far_from_the_page_origin() {
+		let mut widget = widget(Pattern::Dots, 0.5);
+		widget.pan = Vec2::new(3.0e8, -7.0e8);
+		widget.rotation = 0.3;
+		let marks = widget.background.marks(&widget);
+		let dots: Vec<Point2> = marks.iter().flat_map(|marks| marks.dots.iter().copied()).collect();
+		assert!(!dots.is_empty());
+		assert!(dots.iter().any(|dot| dot.x < 64.0 && dot.y < 64.0));
+		assert!(dots.iter().any(|dot| dot.x > 256.0 && dot.y > 176.0));
+	}
+
+	#[test]
+	fn points_snap_to_intersections_on_the_lines() {
+		let grid = widget(Pattern::Grid, 1.0);
+		let snapped = grid.background.nearest_intersection(&grid, Point2::new(-20.0, 47.0));
+		assert_eq!(snapped, Some(Point2::new(-32.0, 32.0)));
+
+		let ruled = widget(Pattern::Ruled, 1.0);
+		let snapped = ruled.background.nearest_intersection(&ruled, Point2::new(-20.0, 47.0));
+		assert_eq!(snapped, Some(Point2::new(-20.0, 32.0)));
+
+		// Every isometric intersection lies on a line of each of the three directions
+		let isometric = widget(Pattern::Isometric, 1.0);
+		let spacing = 32.0 * TRIANGLE_HEIGHT;
+		for point in [Point2::new(30.0, 10.0), Point2::new(-75.0, 300.0), Point2::new(1000.5, -0.5)] {
+			let snapped = isometric.background.nearest_intersection(&isometric, point).unwrap();
+			assert!((snapped - point).norm() <= 32.0 / 3f64.sqrt() + 1e-9);
+			for &(angle, _) in Pattern::Isometric.families() {
+				let offset = snapped.coords.dot(&Vec2::new(-angle.sin(), angle.cos())) / spacing;
+				assert!(
+					(offset - offset.round()).abs() < 1e-9,
+					"{snapped} is off the lines at {angle}"
+				);
+			}
+		}
+
+		let plain = widget(Pattern::Plain, 1.0);
+		assert_eq!(plain.background.nearest_intersection(&plain, Point2::new(1.0, 2.0)), None);
+	}
+
+	#[test]
+	fn zooming_far_out_keeps_marks_apart() {
+		let widget = widget(Pattern::Isometric, 1.0 / 256.0);
+		let lines: usize = widget.background.marks(&widget).iter().map(|marks| marks.lines.len()).sum();
+		assert!(lines > 0 && lines < 400, "{lines} lines");
+	}
+}
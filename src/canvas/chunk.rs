@@ -0,0 +1,72 @@
+use linalg::na::{Affine2, Rotation2, Scale2, Translation2};
+use linalg::prelude::*;
+
+/// Side length in page units of the square chunks that strokes are stored relative to
+pub const CHUNK_SIZE: f64 = 4096.0;
+
+/// Get the origin of the chunk containing a page point
+pub fn chunk_origin(point: Point2) -> Vec2 {
+	Vec2::new(
+		(point.x / CHUNK_SIZE).floor() * CHUNK_SIZE,
+		(point.y / CHUNK_SIZE).floor() * CHUNK_SIZE,
+	)
+}
+
+/// Transform chunk-local coordinates to widget coordinates.
+///
+/// The chunk origin is rebased against the pan in f64 before anything is handed to the renderer, so the f32
+/// coefficients vello ends up with stay small for any chunk near the view, no matter how far it is from the page origin.
+pub fn local_to_widget(origin: Vec2, pan: Vec2, zoom: f64, rotation: f64) -> Affine2<f64> {
+	let offset = origin - pan;
+	Affine2::from_matrix_unchecked(
+		Scale2::new(zoom, zoom).to_homogeneous()
+			* Rotation2::new(-rotation).to_homogeneous()
+			* Translation2::new(offset.x, offset.y).to_homogeneous(),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Apply a transform the way the renderer does, with f32 coefficients and coordinates
+	fn apply_f32(transform: Affine2<f64>, local: Point2) -> (f64, f64) {
+		let m = transform.matrix().map(|v| v as f32);
+		let (x, y) = (local.x as f32, local.y as f32);
+		(
+			(m[(0, 0)] * x + m[(0, 1)] * y + m[(0, 2)]) as f64,
+			(m[(1, 0)] * x + m[(1, 1)] * y + m[(1, 2)]) as f64,
+		)
+	}
+
+	#[test]
+	fn chunk_origin_rounds_down() {
+		assert_eq!(chunk_origin(Point2::new(10.0, 4100.0)), Vec2::new(0.0, CHUNK_SIZE));
+		assert_eq!(
+			chunk_origin(Point2::new(-1.0, -CHUNK_SIZE)),
+			Vec2::new(-CHUNK_SIZE, -CHUNK_SIZE)
+		);
+	}
+
+	#[test]
+	fn drawing_far_from_origin_is_stable() {
+		let zoom = 4.0;
+		for (base, rotation) in [(0.0, 0.0), (3.0e6, 0.0), (-7.5e6, 1.0), (1.0e9, -2.5)] {
+			let pan = Vec2::new(base - 200.0, base - 100.0);
+			let origin = chunk_origin(Point2::new(base, base));
+			let transform = local_to_widget(origin, pan, zoom, rotation);
+
+			for i in 0..100 {
+				let page = Point2::new(base + i as f64 * 0.01, base + i as f64 * 0.013);
+				let (x, y) = apply_f32(transform, page - origin);
+				let expected = Rotation2::new(-rotation) * (page.coords - pan) * zoom;
+				assert!(
+					(x - expected.x).abs() < 0.01 && (y - expected.y).abs() < 0.01,
+					"sample {i} at {base} rendered at ({x}, {y}) instead of ({}, {})",
+					expected.x,
+					expected.y
+				);
+			}
+		}
+	}
+}
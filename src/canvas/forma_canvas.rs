@@ -1,249 +1,191 @@
-use forma_render::{prelude::Point, PathBuilder, Order, gpu::Renderer, Composition, styling::Color, Path};
-use linalg::Vec2;
-use wgpu::{Texture, TextureView, TextureFormat};
+use forma_render::{
+	gpu::Renderer,
+	prelude::Point,
+	styling::{Color, Fill, FillRule, Func, Props, Style},
+	Composition, Layer as FormaLayer, Order, PathBuilder,
+};
+use linalg::prelude::*;
+use wgpu::TextureFormat;
 
-use crate::{Graphics, pen::{PenEvent, flat_pressure_curve}};
+use super::backend::{BackendKind, CanvasBackend, OutputTexture, StrokeHooks, ViewState};
+use super::outline::{marks_polygons, stroke_polygons};
+use super::{CanvasWidget, Layer};
+use crate::util::timeit;
+use crate::Graphics;
 
-pub struct Canvas {
-	width: u32,
-	height: u32,
-	output: Texture,
-	output_view: TextureView,
+/// Forma orders kept for the layers of the background pattern, one per shade of its marks, below the strokes
+const BACKGROUND_ORDERS: usize = 2;
+
+/// Renders the canvas with forma, as one forma layer per stroke over one per shade of the background pattern
+pub struct FormaBackend {
 	renderer: Renderer,
-	pub composition: Composition,
-	current_stroke: Option<StrokeInProgress>,
-	next_order: u32,
+	composition: Composition,
+	output: OutputTexture,
+	/// Number of background layers in use
+	background_count: usize,
+	/// Whether the forma layer of each stroke has to be built again, in drawing order. Every stroke has a layer at the
+	/// order after the background ones plus its index, which may be empty until it is built.
+	stale: Vec<bool>,
+	/// The view the layers were built for
+	view: Option<ViewState>,
 }
 
-impl Canvas {
-	pub fn new(graphics: &Graphics, width: u32, height: u32) -> anyhow::Result<Self> {
-		let (output, output_view) = Self::create_texture(graphics, width, height);
-		
-		let renderer = Renderer::new(&graphics.device, TextureFormat::Bgra8UnormSrgb, false);
-		let composition = Composition::new();
-		
-		Ok(Self {
-			width,
-			height,
-			output,
-			output_view,
-			renderer,
-			composition,
-			current_stroke: None,
-			next_order: 1,
-		})
-	}
-	
-	fn create_texture(graphics: &Graphics, width: u32, height: u32) -> (Texture, TextureView) {
-		let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
-			label: Some("Canvas Output"),
-			size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
-			mip_level_count: 1,
-			sample_count: 1,
-			dimension: wgpu::TextureDimension::D2,
-			format: wgpu::TextureFormat::Bgra8UnormSrgb,
-			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
-		});
-		let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
-			label: Some("Canvas Output View"),
-			format: Some(wgpu::TextureFormat::Bgra8UnormSrgb),
-			dimension: Some(wgpu::TextureViewDimension::D2),
-			aspect: wgpu::TextureAspect::All,
-			base_mip_level: 0,
-			mip_level_count: None,
-			base_array_layer: 0,
-			array_layer_count: None,
-		});
-		(texture, texture_view)
-	}
-	
-	pub fn get_output(&self) -> &Texture {
-		&self.output
-	}
-	
-	pub fn resize(&mut self, graphics: &Graphics, new_width: u32, new_height: u32) {
-		let (new_texture, new_texture_view) = Self::create_texture(graphics, new_width, new_height);
-		self.output = new_texture;
-		self.output_view = new_texture_view;
-		self.width = new_width;
-		self.height = new_height;
-		self.render(graphics);
+impl FormaBackend {
+	pub fn new(graphics: &Graphics, width: u32, height: u32) -> Self {
+		Self {
+			renderer: Renderer::new(&graphics.device, TextureFormat::Rgba8Unorm, false),
+			composition: Composition::new(),
+			output: OutputTexture::new(graphics, width, height),
+			background_count: 0,
+			stale: Vec::new(),
+			view: None,
+		}
 	}
-	
-	pub fn render(&mut self, graphics: &Graphics) {
-		self.renderer.render_to_texture(
-			&mut self.composition,
-			&graphics.device,
-			&graphics.queue,
-			&self.output_view,
-			self.width,
-			self.height,
-			Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 },
-		);
+
+	/// Put a layer at an order, leaving it out if forma has no such order. `render` reports that instead.
+	fn insert_layer(&mut self, order: usize, layer: FormaLayer) {
+		if let Ok(order) = Order::new(order as u32) {
+			self.composition.insert(order, layer);
+		}
 	}
-	
-	pub fn next_order(&mut self) -> Order {
-		let order = Order::new(self.next_order).unwrap();
-		self.next_order += 1;
-		order
+
+	fn remove_layer(&mut self, order: usize) -> Option<FormaLayer> {
+		self.composition.remove(Order::new(order as u32).ok()?)
 	}
-	
-	pub fn start_stroke(&mut self) {
-		let order = self.next_order();
-		let layer = self.composition.create_layer();
-		self.composition.insert(order, layer);
-		self.current_stroke = Some(StrokeInProgress { events: Vec::new() })
+
+	/// Build a forma layer filling polygons in widget coordinates
+	fn build_layer(&mut self, polygons: Vec<Vec<Point2>>, color: vello::peniko::Color) -> FormaLayer {
+		let mut builder = PathBuilder::new();
+		for polygon in polygons {
+			builder.move_to(Point::new(polygon[0].x as f32, polygon[0].y as f32));
+			for point in &polygon[1..] {
+				builder.line_to(Point::new(point.x as f32, point.y as f32));
+			}
+		}
+
+		let [r, g, b, a] = [color.r, color.g, color.b, color.a].map(|v| v as f32 / 255.0);
+		let mut layer = self.composition.create_layer();
+		layer.insert(&builder.build()).set_props(Props {
+			fill_rule: FillRule::NonZero,
+			func: Func::Draw(Style {
+				fill: Fill::Solid(Color { r, g, b, a }),
+				..Default::default()
+			}),
+		});
+		layer
 	}
-	
-	pub fn move_stroke(&mut self, point: Vec2, pressure: f32) {
-		let width = 4.0;
-		
-		if let Some(ref mut progress) = self.current_stroke {
-			progress.move_to(point, pressure);
-			/* if progress.events.len() >= 2 {
-				let end = progress.events.len() - 1;
-				if (progress.events[end].pos - progress.events[end - 1].pos).norm() < width * 2.0 {
-					progress.events.pop();
-					return;
-				}
-				let path = segment_to_path([progress.events[end - 1], progress.events[end]], width, &flat_pressure_curve);
-				dbg!(&path);
-				let layer = self.composition.get_mut(Order::new(self.next_order - 1).unwrap()).unwrap();
-				layer.insert(&path);
-			} */
+
+	/// Move the layer of the stroke at index `from` to index `to`
+	fn move_stroke_layer(&mut self, from: usize, to: usize) {
+		if let Some(layer) = self.remove_layer(BACKGROUND_ORDERS + from) {
+			self.insert_layer(BACKGROUND_ORDERS + to, layer);
 		}
 	}
-	
-	pub fn end_stroke(&mut self) {
-		if let Some(progress) = self.current_stroke.take() {
-			if progress.events.len() < 2 {
-				return;
-			}
-			
-			let path = pen_stroke_to_path(&progress.events, 4.0, &flat_pressure_curve);
-			let layer = self.composition.get_mut(Order::new(self.next_order - 1).unwrap()).unwrap();
-			layer.insert(&path);
+
+	/// Build the background layers for the view
+	fn update_background(&mut self, widget: &CanvasWidget) {
+		let marks = widget.background.marks(widget);
+		for (i, marks) in marks.iter().enumerate().take(BACKGROUND_ORDERS) {
+			let layer = self.build_layer(marks_polygons(marks), marks.color);
+			self.insert_layer(i, layer);
+		}
+		for i in marks.len()..self.background_count {
+			self.remove_layer(i);
 		}
+		self.background_count = marks.len().min(BACKGROUND_ORDERS);
 	}
-	
-	pub fn width(&self) -> u32 { self.width }
-	
-	pub fn height(&self) -> u32 { self.height }
 }
 
-struct StrokeInProgress {
-	events: Vec<PenEvent>,
-}
+impl StrokeHooks for FormaBackend {
+	fn reset_strokes(&mut self, layers: &[Layer]) {
+		for i in 0..self.stale.len() {
+			self.remove_layer(BACKGROUND_ORDERS + i);
+		}
+		self.stale = vec![true; layers.len()];
+		for i in 0..layers.len() {
+			let layer = self.composition.create_layer();
+			self.insert_layer(BACKGROUND_ORDERS + i, layer);
+		}
+	}
 
-impl StrokeInProgress {
-	pub fn new() -> Self {
-		Self {
-			events: Vec::new(),
+	fn insert_stroke(&mut self, index: usize, _layer: &Layer) {
+		for i in (index..self.stale.len()).rev() {
+			self.move_stroke_layer(i, i + 1);
 		}
+		self.stale.insert(index, true);
+		let layer = self.composition.create_layer();
+		self.insert_layer(BACKGROUND_ORDERS + index, layer);
 	}
-	
-	pub fn move_to(&mut self, point: Vec2, pressure: f32) {
-		self.events.push(PenEvent {
-			pos: point,
-			pressure,
-			speed: 1.0,
-		});
+
+	fn remove_stroke(&mut self, index: usize) {
+		self.remove_layer(BACKGROUND_ORDERS + index);
+		self.stale.remove(index);
+		for i in index..self.stale.len() {
+			self.move_stroke_layer(i + 1, i);
+		}
 	}
-}
 
-fn segment_to_path(segment: [PenEvent; 2], width: f32, pressure_curve: &dyn Fn(f32) -> f32) -> Path {
-	let side = width / 2.0;
-	let extended = 0.0;
-	
-	let mut path_builder = PathBuilder::new();
-	let a = segment[0].pos;
-	let b = segment[1].pos;
-	let dir = b - a;
-	let perp = Vec2::new(dir.y, -dir.x).normalize();
-	path_builder.move_to(vec_to_point(a - dir * extended - perp * side * pressure_curve(segment[0].pressure)));
-	path_builder.line_to(vec_to_point(a - dir * extended + perp * side * pressure_curve(segment[0].pressure)));
-	path_builder.line_to(vec_to_point(b + dir * extended + perp * side * pressure_curve(segment[1].pressure)));
-	path_builder.line_to(vec_to_point(b + dir * extended - perp * side * pressure_curve(segment[1].pressure)));
-	path_builder.build()
+	fn replace_stroke(&mut self, index: usize, _layer: &Layer) {
+		self.stale[index] = true;
+	}
 }
 
-fn pen_stroke_to_path(stroke: &[PenEvent], width: f32, pressure_curve: &dyn Fn(f32) -> f32) -> Path {
-	let side = width / 2.0;
-	
-	assert!(stroke.len() >= 2);
-	let mut path_builder = PathBuilder::new();
-	let a = stroke[0].pos;
-	let b = stroke[1].pos;
-	let dir = b - a;
-	let perp = Vec2::new(dir.y, -dir.x).normalize();
-	path_builder.move_to(vec_to_point(a - perp * side * pressure_curve(stroke[0].pressure)));
-	path_builder.line_to(vec_to_point(a + perp * side));
-	for i in 1..stroke.len() {
-		let a = stroke[i - 1].pos;
-		let b = stroke[i].pos;
-		let dir = b - a;
-		let perp = Vec2::new(dir.y, -dir.x).normalize();
-		path_builder.line_to(vec_to_point(b - perp * side * pressure_curve(stroke[i].pressure)));
+impl CanvasBackend for FormaBackend {
+	fn kind(&self) -> BackendKind {
+		BackendKind::Forma
 	}
-	let a = stroke[stroke.len() - 1].pos;
-	let b = stroke[stroke.len() - 2].pos;
-	let dir = b - a;
-	let perp = Vec2::new(dir.y, -dir.x).normalize();
-	path_builder.line_to(vec_to_point(b - perp * side * pressure_curve(stroke[stroke.len() - 2].pressure)));
-	for i in (0..(stroke.len() - 1)).rev() {
-		let a = stroke[i + 1].pos;
-		let b = stroke[i].pos;
-		let dir = b - a;
-		let perp = Vec2::new(dir.y, -dir.x).normalize();
-		path_builder.line_to(vec_to_point(b - perp * side * pressure_curve(stroke[i].pressure)));
+
+	fn resize(&mut self, graphics: &Graphics, width: u32, height: u32) {
+		self.output = OutputTexture::new(graphics, width, height);
 	}
-	path_builder.build()
-}
 
-fn point_to_vec(point: Point) -> Vec2 {
-	Vec2::new(point.x, point.y)
-}
+	fn render(&mut self, graphics: &Graphics, widget: &CanvasWidget) -> anyhow::Result<()> {
+		// Forma paths can't be transformed cheaply, so strokes are built in widget coordinates and all of them have to
+		// be built again when the view changes. Otherwise only the strokes that changed are.
+		if Order::new((BACKGROUND_ORDERS + self.stale.len()) as u32).is_err() {
+			return Err(anyhow::format_err!("Too many strokes for forma"));
+		}
 
-fn vec_to_point(vec: Vec2) -> Point {
-	Point::new(vec.x, vec.y)
-}
+		let view = ViewState::new(widget);
+		if self.view.as_ref() != Some(&view) {
+			self.update_background(widget);
+			self.stale.fill(true);
+			self.view = Some(view);
+		}
 
-struct StrokeData {
-	// invariant: len >= 2
-	points: Vec<StrokePoint>,
-	segments: Vec<Segment>,
-}
+		timeit!("build stroke layers", {
+			for (i, layer) in widget.canvas.layers().iter().enumerate() {
+				if !std::mem::replace(&mut self.stale[i], false) {
+					continue;
+				}
+				let to_widget = widget.layer_transform(layer.origin());
+				let polygons = stroke_polygons(layer, &to_widget, widget.pixel_scale());
+				let forma_layer = self.build_layer(polygons, layer.color());
+				self.insert_layer(BACKGROUND_ORDERS + i, forma_layer);
+			}
+		});
 
-impl StrokeData {
-	pub fn segment(&self, i: usize) -> [StrokePoint; 2] {
-		[self.points[i], self.points[i + 1]]
+		timeit!(
+			"render canvas",
+			self.renderer.render_to_texture(
+				&mut self.composition,
+				&graphics.device,
+				&graphics.queue,
+				self.output.get_texture_view(),
+				self.output.get_width(),
+				self.output.get_height(),
+				Color {
+					r: 1.0,
+					g: 1.0,
+					b: 1.0,
+					a: 1.0,
+				},
+			)
+		);
+		Ok(())
 	}
-}
 
-#[derive(Debug, Clone, Copy)]
-struct StrokePoint {
-	pos: Vec2,
-	thickness: f32,
-}
-
-#[derive(Debug, Clone, Copy)]
-struct Segment {
-	// negative = left, positive = right
-	bias: f32,
-}
-
-fn clean_stroke(stroke: StrokeData) {
-	loop {
-		// find the segment with two points closest together
-		let dense_i = (0..stroke.points.len()).min_by_key(|&i| ((stroke.segment(i)[1].pos - stroke.segment(i)[0].pos).norm_squared() * 100000.0) as u64).unwrap();
-		let dense_seg = stroke.segment(dense_i);
-		let norm = (dense_seg[1].pos - dense_seg[0].pos).norm();
-		if norm < dense_seg[0].thickness + dense_seg[1].thickness {
-			break;
-		}
+	fn output(&self) -> &OutputTexture {
+		&self.output
 	}
 }
-
-fn stroke_data_to_path(stroke_data: &StrokeData) {
-	
-}
\ No newline at end of file
@@ -0,0 +1,267 @@
+//! Renders canned strokes through every compiled-in backend and compares them with the reference images in
+//! `tests/golden`. The references come from the vello backend and are shared by all backends. A missing reference is
+//! a failure. The GPU backends are skipped on machines without any graphics adapter, while a software one such as
+//! lavapipe or llvmpipe is enough to run them. Run the tests with `SKYBOARD_BLESS=1` on a machine with a graphics
+//! adapter to write the references from the current vello output.
+
+use std::path::{Path, PathBuf};
+
+use linalg::prelude::*;
+
+use super::{BackendKind, CanvasWidget, Pattern};
+use crate::headless::{HeadlessRenderer, RgbaImage};
+use crate::pen::PenEvent;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+/// Pixels whose weighted color difference is below this are considered equal, to allow for antialiasing differences
+/// between adapters
+const PIXEL_TOLERANCE: f64 = 0.1;
+/// Fraction of pixels that may differ by more than `PIXEL_TOLERANCE`
+const MAX_DIFFERENT_FRACTION: f64 = 0.002;
+/// The backend whose output the references are made from
+const REFERENCE_BACKEND: BackendKind = BackendKind::Vello;
+
+struct Case {
+	name: &'static str,
+	zoom: f64,
+	rotation: f64,
+	/// Whether to leave the stroke in progress instead of committing it
+	in_progress: bool,
+	background: Pattern,
+	/// Pen samples in widget space, fed to the canvas like the pen tool does
+	events: Vec<PenEvent>,
+}
+
+impl Case {
+	fn new(name: &'static str, events: Vec<PenEvent>) -> Self {
+		Self {
+			name,
+			zoom: 1.0,
+			rotation: 0.0,
+			in_progress: false,
+			background: Pattern::Plain,
+			events,
+		}
+	}
+}
+
+/// Sample a pen path over `t` in 0..=1, with the pen moving at a steady speed
+fn sample(count: usize, f: impl Fn(f64) -> (f64, f64, f32)) -> Vec<PenEvent> {
+	(0..count)
+		.map(|i| {
+			let (x, y, pressure) = f(i as f64 / (count - 1) as f64);
+			PenEvent {
+				pos: Point2::new(x, y),
+				pressure,
+				speed: 1.0,
+			}
+		})
+		.collect()
+}
+
+fn cases() -> Vec<Case> {
+	// Pressure swings around `pressure` so the width changes along every stroke
+	let wave = |pressure: f32| {
+		move |t: f64| {
+			(
+				24.0 + t * 208.0,
+				96.0 + (t * std::f64::consts::TAU * 2.0).sin() * 48.0,
+				pressure * (0.6 + 0.4 * (t * std::f64::consts::TAU * 3.0).cos() as f32),
+			)
+		}
+	};
+	let zigzag = |t: f64| {
+		let phase = (t * 6.0).fract();
+		let pressure = 0.15 + 0.3 * phase as f32;
+		(24.0 + t * 208.0, 48.0 + (1.0 - (phase * 2.0 - 1.0).abs()) * 96.0, pressure)
+	};
+
+	vec![
+		Case::new("pressure_ramp", sample(64, |t| (24.0 + t * 208.0, 96.0, t as f32))),
+		Case::new("full_pressure_wave", sample(128, wave(1.0))),
+		Case::new("light_zigzag", sample(96, zigzag)),
+		Case::new("sparse_samples", sample(6, wave(0.7))),
+		Case {
+			zoom: 4.0,
+			..Case::new("zoomed_in", sample(128, wave(0.8)))
+		},
+		Case {
+			zoom: 0.25,
+			..Case::new("zoomed_out", sample(128, wave(0.8)))
+		},
+		Case {
+			rotation: 0.5,
+			..Case::new("rotated", sample(64, |t| (24.0 + t * 208.0, 96.0, 1.0 - t as f32 * 0.5)))
+		},
+		Case {
+			in_progress: true,
+			..Case::new("in_progress", sample(128, wave(0.6)))
+		},
+		Case {
+			zoom: 1.5,
+			rotation: 0.3,
+			background: Pattern::Grid,
+			..Case::new("grid_background", sample(64, wave(0.8)))
+		},
+		Case {
+			zoom: 0.75,
+			background: Pattern::Dots,
+			..Case::new("dots_background", sample(64, zigzag))
+		},
+		Case {
+			background: Pattern::Isometric,
+			..Case::new("isometric_background", sample(64, wave(0.5)))
+		},
+	]
+}
+
+fn case_widget(case: &Case) -> CanvasWidget {
+	let mut widget = CanvasWidget::new(WIDTH, HEIGHT);
+	widget.background.pattern = case.background;
+	let center = widget.center();
+	widget.zoom_around(center, case.zoom);
+	widget.rotate_around(center, case.rotation);
+
+	let transform = widget.transform();
+	widget.canvas.start_stroke();
+	for event in &case.events {
+		widget.canvas.move_stroke(transform * event.pos, event.pressure);
+	}
+	if !case.in_progress {
+		widget.canvas.end_stroke();
+	}
+	widget
+}
+
+fn render_case(renderer: &mut HeadlessRenderer, case: &Case) -> anyhow::Result<RgbaImage> {
+	renderer.render(&case_widget(case))
+}
+
+/// Weighted difference of two pixels in 0..=1, with luma counting more than chroma and alpha
+fn pixel_difference(a: [u8; 4], b: [u8; 4]) -> f64 {
+	let d = |i: usize| (a[i] as f64 - b[i] as f64) / 255.0;
+	let luma = 0.299 * d(0) + 0.587 * d(1) + 0.114 * d(2);
+	let chroma = (d(0) - d(1)).abs().max((d(1) - d(2)).abs());
+	(luma.abs() + 0.25 * chroma).max(d(3).abs())
+}
+
+/// Compare an image to its reference. Returns the fraction of differing pixels and an image highlighting them in red
+/// over a faded copy of the reference.
+fn compare(actual: &RgbaImage, reference: &RgbaImage) -> (f64, RgbaImage) {
+	let mut diff = RgbaImage {
+		width: reference.width,
+		height: reference.height,
+		pixels: Vec::with_capacity(reference.pixels.len()),
+	};
+	let mut different = 0;
+	for y in 0..reference.height {
+		for x in 0..reference.width {
+			let expected = reference.pixel(x, y);
+			if pixel_difference(actual.pixel(x, y), expected) > PIXEL_TOLERANCE {
+				different += 1;
+				diff.pixels.extend_from_slice(&[255, 0, 0, 255]);
+			} else {
+				let luma = (expected[0] as u32 * 299 + expected[1] as u32 * 587 + expected[2] as u32 * 114) / 1000;
+				let faded = (192 + luma / 4) as u8;
+				diff.pixels.extend_from_slice(&[faded, faded, faded, 255]);
+			}
+		}
+	}
+	(different as f64 / (reference.width * reference.height) as f64, diff)
+}
+
+fn reference_dir() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+/// Check a rendering against its reference, returning a description of the failure if it doesn't match
+fn check(backend: BackendKind, case: &str, actual: &RgbaImage) -> anyhow::Result<Option<String>> {
+	let reference_path = reference_dir().join(format!("{case}.png"));
+	let name = format!("{case}-{}", backend.name());
+	if backend == REFERENCE_BACKEND && std::env::var_os("SKYBOARD_BLESS").is_some() {
+		std::fs::create_dir_all(reference_dir())?;
+		actual.save_png(&reference_path)?;
+		eprintln!("Wrote reference image '{}'", reference_path.display());
+		return Ok(None);
+	}
+
+	if !reference_path.exists() {
+		return Ok(Some(format!(
+			"{name}: no reference image at '{}', run the vello golden test with SKYBOARD_BLESS=1 to create it",
+			reference_path.display()
+		)));
+	}
+
+	let reference = RgbaImage::load_png(&reference_path)?;
+	let failure = if (reference.width, reference.height) != (actual.width, actual.height) {
+		Some(format!(
+			"{name}: size {}x{} differs from reference {}x{}",
+			actual.width, actual.height, reference.width, reference.height
+		))
+	} else {
+		let (different, diff) = compare(actual, &reference);
+		if different <= MAX_DIFFERENT_FRACTION {
+			return Ok(None);
+		}
+		std::fs::create_dir_all(output_dir())?;
+		diff.save_png(&output_dir().join(format!("{name}-diff.png")))?;
+		Some(format!("{name}: {:.2}% of pixels differ", different * 100.0))
+	};
+
+	std::fs::create_dir_all(output_dir())?;
+	let actual_path = output_dir().join(format!("{name}-actual.png"));
+	actual.save_png(&actual_path)?;
+	Ok(failure.map(|failure| format!("{failure}, see '{}'", actual_path.display())))
+}
+
+#[test]
+fn strokes_match_reference_images() {
+	let mut renderer = match HeadlessRenderer::new(BackendKind::preferred().unwrap()) {
+		Ok(renderer) => renderer,
+		Err(e) => {
+			eprintln!("Skipping the golden image test on the GPU, there is no graphics adapter: {e}");
+			return;
+		}
+	};
+
+	let mut failures = Vec::new();
+	for backend in BackendKind::available() {
+		renderer.set_backend(backend).unwrap();
+		for case in cases() {
+			let actual = render_case(&mut renderer, &case).unwrap();
+			failures.extend(check(backend, case.name, &actual).unwrap());
+		}
+	}
+	assert!(
+		failures.is_empty(),
+		"Renderings differ from the references:\n{}",
+		failures.join("\n")
+	);
+}
+
+/// The CPU rasterizer is also checked on its own, since it's what machines without a graphics adapter draw with
+#[cfg(feature = "backend-raster")]
+#[test]
+fn cpu_raster_matches_reference_images() {
+	let mut canvas = super::RasterCanvas::new(WIDTH, HEIGHT);
+	let mut failures = Vec::new();
+	for case in cases() {
+		canvas.render(&case_widget(&case));
+		let actual = RgbaImage {
+			width: WIDTH,
+			height: HEIGHT,
+			pixels: canvas.pixels().to_vec(),
+		};
+		failures.extend(check(BackendKind::Raster, case.name, &actual).unwrap());
+	}
+	assert!(
+		failures.is_empty(),
+		"Renderings differ from the references:\n{}",
+		failures.join("\n")
+	);
+}
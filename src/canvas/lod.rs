@@ -0,0 +1,150 @@
+use linalg::prelude::*;
+
+use crate::pen::{flat_pressure_curve, PenEvent, STROKE_WIDTH};
+
+/// Maximum deviation in page units of the outline of each simplified stroke variant from the original, finest first
+pub const LOD_TOLERANCES: [f64; 4] = [1.0, 4.0, 16.0, 64.0];
+
+/// Pick the coarsest level of detail whose error stays under a pixel at the given zoom, or `None` if the full
+/// stroke should be drawn. Since the finest error is a page unit, simplified strokes are only drawn zoomed out.
+pub fn pick_lod(zoom: f64) -> Option<usize> {
+	LOD_TOLERANCES.iter().rposition(|&tolerance| tolerance * zoom < 1.0)
+}
+
+/// Get how far the outline of a stroke at `event` is from the outline of the segment from `a` to `b`: the distance
+/// of the centerlines plus the difference between the half width at `event` and the one interpolated along the
+/// segment
+fn outline_distance(event: &PenEvent, a: &PenEvent, b: &PenEvent) -> f64 {
+	let half_width = |event: &PenEvent| (STROKE_WIDTH * flat_pressure_curve(event.pressure)) as f64 * 0.5;
+	let ab = b.pos - a.pos;
+	let len_squared = ab.norm_squared();
+	let t = if len_squared == 0.0 {
+		0.0
+	} else {
+		((event.pos - a.pos).dot(&ab) / len_squared).clamp(0.0, 1.0)
+	};
+	let width_error = half_width(event) - (half_width(a) + (half_width(b) - half_width(a)) * t);
+	(event.pos - (a.pos + ab * t)).norm() + width_error.abs()
+}
+
+/// Simplify a stroke with Ramer-Douglas-Peucker so the outline at no dropped sample lies further than `tolerance`
+/// from the result, counting both where the sample is and how wide the stroke is there. The first and last events
+/// are always kept.
+pub fn simplify(events: &[PenEvent], tolerance: f64) -> Vec<PenEvent> {
+	if events.len() <= 2 {
+		return events.to_vec();
+	}
+
+	let mut keep = vec![false; events.len()];
+	keep[0] = true;
+	keep[events.len() - 1] = true;
+
+	let mut stack = vec![(0, events.len() - 1)];
+	while let Some((first, last)) = stack.pop() {
+		let (mut max_dist, mut max_i) = (0.0, first);
+		for i in (first + 1)..last {
+			let dist = outline_distance(&events[i], &events[first], &events[last]);
+			if dist > max_dist {
+				max_dist = dist;
+				max_i = i;
+			}
+		}
+
+		if max_dist > tolerance {
+			keep[max_i] = true;
+			stack.push((first, max_i));
+			stack.push((max_i, last));
+		}
+	}
+
+	events
+		.iter()
+		.zip(keep)
+		.filter_map(|(&event, keep)| keep.then_some(event))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A wavy stroke with varying pressure and enough samples for every level of detail to drop some
+	fn wavy_stroke() -> Vec<PenEvent> {
+		(0..500)
+			.map(|i| {
+				let x = i as f64;
+				PenEvent {
+					pos: Point2::new(x, (x * 0.05).sin() * 80.0 + (x * 0.7).sin() * 3.0),
+					pressure: 0.5 + (x * 0.02).sin() as f32 * 0.4,
+					speed: 0.0,
+				}
+			})
+			.collect()
+	}
+
+	fn event(x: f64, y: f64, pressure: f32) -> PenEvent {
+		PenEvent {
+			pos: Point2::new(x, y),
+			pressure,
+			speed: 0.0,
+		}
+	}
+
+	#[test]
+	fn simplified_strokes_stay_within_tolerance() {
+		let events = wavy_stroke();
+		for tolerance in LOD_TOLERANCES {
+			let simplified = simplify(&events, tolerance);
+			assert!(simplified.len() < events.len(), "nothing was dropped at {tolerance}");
+			for event in &events {
+				let dist = simplified
+					.windows(2)
+					.map(|pair| outline_distance(event, &pair[0], &pair[1]))
+					.fold(f64::INFINITY, f64::min);
+				assert!(
+					dist <= tolerance,
+					"{:?} is {dist} from the stroke simplified to {tolerance}",
+					event.pos
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn simplify_keeps_endpoints() {
+		let events = wavy_stroke();
+		for tolerance in LOD_TOLERANCES {
+			let simplified = simplify(&events, tolerance);
+			assert_eq!(simplified.first().unwrap().pos, events.first().unwrap().pos);
+			assert_eq!(simplified.last().unwrap().pos, events.last().unwrap().pos);
+		}
+		assert_eq!(simplify(&events[..2], 1000.0).len(), 2);
+	}
+
+	#[test]
+	fn simplify_keeps_changes_in_width() {
+		// A straight stroke that gets thick in the middle keeps the samples where the width changes
+		let events: Vec<_> = (0..=20)
+			.map(|i| event(i as f64 * 10.0, 0.0, if (8..=12).contains(&i) { 1.0 } else { 0.2 }))
+			.collect();
+		let simplified = simplify(&events, LOD_TOLERANCES[0]);
+		let xs: Vec<_> = simplified.iter().map(|event| event.pos.x).collect();
+		assert_eq!(xs, [0.0, 70.0, 80.0, 120.0, 130.0, 200.0]);
+
+		// The same stroke at constant pressure is a single segment
+		let even: Vec<_> = events.iter().map(|e| event(e.pos.x, e.pos.y, 0.5)).collect();
+		assert_eq!(simplify(&even, LOD_TOLERANCES[0]).len(), 2);
+	}
+
+	#[test]
+	fn pick_lod_follows_zoom() {
+		assert_eq!(pick_lod(1.0e6), None);
+		assert_eq!(pick_lod(1.5), None);
+		assert_eq!(pick_lod(1.0), None);
+		assert_eq!(pick_lod(0.5), Some(0));
+		assert_eq!(pick_lod(0.25), Some(0));
+		assert_eq!(pick_lod(0.2), Some(1));
+		assert_eq!(pick_lod(1.0 / 64.0), Some(2));
+		assert_eq!(pick_lod(1.0e-6), Some(3));
+	}
+}
@@ -0,0 +1,57 @@
+use linalg::na::Affine2;
+use linalg::prelude::*;
+
+use super::{Layer, Marks};
+use crate::pen::segment_width;
+
+/// Largest distance in pixels between the outline of a round cap and the circle it approximates
+const CAP_TOLERANCE: f64 = 0.2;
+
+/// Build the outline of a stroke in widget coordinates, for backends that fill polygons instead of stroking lines.
+/// Like the vello encoding, every segment is a line as wide as `segment_width` with round caps at both ends, so the
+/// result is one quad per segment and one disc per cap. All polygons are convex and wound the same way.
+pub fn stroke_polygons(layer: &Layer, to_widget: &Affine2<f64>, zoom: f64) -> Vec<Vec<Point2>> {
+	let mut polygons = Vec::new();
+	for pair in layer.events().windows(2) {
+		let radius = segment_width(&pair[0], &pair[1]) as f64 * 0.5 * zoom;
+		let a = to_widget * pair[0].pos;
+		let b = to_widget * pair[1].pos;
+
+		let dir = b - a;
+		if dir.norm() > 0.0 {
+			let normal = Vec2::new(-dir.y, dir.x).normalize() * radius;
+			polygons.push(vec![a - normal, b - normal, b + normal, a + normal]);
+		}
+		polygons.push(disc(a, radius));
+		polygons.push(disc(b, radius));
+	}
+	polygons
+}
+
+/// Build the outline of the marks of a background pattern in widget coordinates, one quad per line and one disc per dot
+pub fn marks_polygons(marks: &Marks) -> Vec<Vec<Point2>> {
+	let mut polygons = Vec::new();
+	for &(a, b) in &marks.lines {
+		let dir = b - a;
+		if dir.norm() > 0.0 {
+			let normal = Vec2::new(-dir.y, dir.x).normalize() * (marks.line_width * 0.5);
+			polygons.push(vec![a - normal, b - normal, b + normal, a + normal]);
+		}
+	}
+	polygons.extend(marks.dots.iter().map(|&center| disc(center, marks.dot_radius)));
+	polygons
+}
+
+fn disc(center: Point2, radius: f64) -> Vec<Point2> {
+	let sides = if radius <= CAP_TOLERANCE {
+		4
+	} else {
+		((std::f64::consts::PI / (1.0 - CAP_TOLERANCE / radius).acos()).ceil() as usize).clamp(4, 64)
+	};
+	(0..sides)
+		.map(|i| {
+			let angle = i as f64 / sides as f64 * std::f64::consts::TAU;
+			center + Vec2::new(angle.cos(), angle.sin()) * radius
+		})
+		.collect()
+}
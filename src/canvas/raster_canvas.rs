@@ -1,242 +1,271 @@
-use forma_render::{prelude::Point, PathBuilder, Order, gpu::Renderer, Composition, styling::Color, Path};
-use linalg::Vec2;
-use wgpu::{Texture, TextureView, TextureFormat};
+use linalg::prelude::*;
+use vello::peniko::Color;
 
-use crate::{Graphics, pen::{PenEvent, flat_pressure_curve}};
+use super::backend::{BackendKind, CanvasBackend, OutputTexture, StrokeHooks, ViewState};
+use super::{CanvasWidget, Layer};
+use crate::pen::segment_width;
+use crate::util::{segment_distance, timeit};
+use crate::Graphics;
 
-pub struct Canvas {
+/// A canvas rendered on the CPU into RGBA pixels, without needing a graphics adapter.
+///
+/// Strokes are drawn the way vello draws them: every segment is a line as wide as `segment_width` with round caps,
+/// antialiased by how much of each pixel it covers and blended over what was drawn before. Since later segments only
+/// ever go on top, strokes started or gaining samples on top of the others are drawn onto the previous pixels. Any
+/// other change, and any change to the view, draws everything again.
+pub struct RasterCanvas {
 	width: u32,
 	height: u32,
-	output: Texture,
-	output_view: TextureView,
-	strokes: Vec<Vec<PenEvent>>,
-	rasters: Vec<Raster>,
-	current_stroke: Option<StrokeInProgress>,
+	/// RGBA pixels with tightly packed rows
+	pixels: Vec<u8>,
+	/// The view the pixels show, or `None` if they have to be drawn again
+	view: Option<ViewState>,
+	/// Number of strokes the `StrokeHooks` know about
+	stroke_count: usize,
+	/// Samples of the top stroke already in the pixels
+	drawn_samples: usize,
+	/// Strokes to draw on top of the pixels, from the sample given onwards
+	pending: Vec<(usize, usize)>,
 }
 
-impl Canvas {
-	pub fn new(graphics: &Graphics, width: u32, height: u32) -> anyhow::Result<Self> {
-		let (output, output_view) = Self::create_texture(graphics, width, height);
-		
-		Ok(Self {
+impl RasterCanvas {
+	pub fn new(width: u32, height: u32) -> Self {
+		Self {
 			width,
 			height,
-			output,
-			output_view,
-			current_stroke: None,
-		})
-	}
-	
-	fn create_texture(graphics: &Graphics, width: u32, height: u32) -> (Texture, TextureView) {
-		let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
-			label: Some("Canvas Output"),
-			size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
-			mip_level_count: 1,
-			sample_count: 1,
-			dimension: wgpu::TextureDimension::D2,
-			format: wgpu::TextureFormat::Bgra8UnormSrgb,
-			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
-		});
-		let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
-			label: Some("Canvas Output View"),
-			format: Some(wgpu::TextureFormat::Bgra8UnormSrgb),
-			dimension: Some(wgpu::TextureViewDimension::D2),
-			aspect: wgpu::TextureAspect::All,
-			base_mip_level: 0,
-			mip_level_count: None,
-			base_array_layer: 0,
-			array_layer_count: None,
-		});
-		(texture, texture_view)
+			pixels: vec![0; width as usize * height as usize * 4],
+			view: None,
+			stroke_count: 0,
+			drawn_samples: 0,
+			pending: Vec::new(),
+		}
 	}
-	
-	pub fn get_output(&self) -> &Texture {
-		&self.output
+
+	pub fn resize(&mut self, width: u32, height: u32) {
+		*self = Self {
+			stroke_count: self.stroke_count,
+			..Self::new(width, height)
+		};
 	}
-	
-	pub fn resize(&mut self, graphics: &Graphics, new_width: u32, new_height: u32) {
-		let (new_texture, new_texture_view) = Self::create_texture(graphics, new_width, new_height);
-		self.output = new_texture;
-		self.output_view = new_texture_view;
-		self.width = new_width;
-		self.height = new_height;
-		self.render(graphics);
-	}
-	
-	pub fn render(&mut self, graphics: &Graphics) {
-		self.renderer.render_to_texture(
-			&mut self.composition,
-			&graphics.device,
-			&graphics.queue,
-			&self.output_view,
-			self.width,
-			self.height,
-			Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 },
-		);
+
+	pub fn get_width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn get_height(&self) -> u32 {
+		self.height
+	}
+
+	pub fn pixels(&self) -> &[u8] {
+		&self.pixels
 	}
-	
-	pub fn next_order(&mut self) -> Order {
-		let order = Order::new(self.next_order).unwrap();
-		self.next_order += 1;
-		order
-	}
-	
-	pub fn start_stroke(&mut self) {
-		let order = self.next_order();
-		let layer = self.composition.create_layer();
-		self.composition.insert(order, layer);
-		self.current_stroke = Some(StrokeInProgress { events: Vec::new() })
-	}
-	
-	pub fn move_stroke(&mut self, point: Vec2, pressure: f32) {
-		let width = 4.0;
-		
-		if let Some(ref mut progress) = self.current_stroke {
-			progress.move_to(point, pressure);
-			/* if progress.events.len() >= 2 {
-				let end = progress.events.len() - 1;
-				if (progress.events[end].pos - progress.events[end - 1].pos).norm() < width * 2.0 {
-					progress.events.pop();
-					return;
+
+	/// Draw everything
+	pub fn render(&mut self, widget: &CanvasWidget) {
+		timeit!("rasterize canvas", {
+			self.pixels.fill(255);
+			for marks in widget.background.marks(widget) {
+				for &(from, to) in &marks.lines {
+					self.stroke_segment(from, to, marks.line_width * 0.5, marks.color);
+				}
+				for &center in &marks.dots {
+					self.stroke_segment(center, center, marks.dot_radius, marks.color);
 				}
-				let path = segment_to_path([progress.events[end - 1], progress.events[end]], width, &flat_pressure_curve);
-				dbg!(&path);
-				let layer = self.composition.get_mut(Order::new(self.next_order - 1).unwrap()).unwrap();
-				layer.insert(&path);
-			} */
+			}
+			for layer in widget.canvas.layers() {
+				self.draw_layer(widget, layer, 0);
+			}
+		});
+		self.view = Some(ViewState::new(widget));
+		self.pending.clear();
+		self.drawn_samples = widget.canvas.layers().last().map_or(0, |layer| layer.events().len());
+	}
+
+	/// Draw what changed since the last render, as passed on by the `StrokeHooks`
+	pub fn update(&mut self, widget: &CanvasWidget) {
+		if self.view.as_ref() != Some(&ViewState::new(widget)) {
+			self.render(widget);
+			return;
+		}
+
+		let layers = widget.canvas.layers();
+		for (index, first_sample) in std::mem::take(&mut self.pending) {
+			self.draw_layer(widget, &layers[index], first_sample);
+		}
+		self.drawn_samples = layers.last().map_or(0, |layer| layer.events().len());
+	}
+
+	/// Draw the segments of a stroke ending at each sample from `first_sample` onwards
+	fn draw_layer(&mut self, widget: &CanvasWidget, layer: &Layer, first_sample: usize) {
+		let to_widget = widget.layer_transform(layer.origin());
+		let events = layer.events().get(first_sample.saturating_sub(1)..).unwrap_or_default();
+		for pair in events.windows(2) {
+			let radius = segment_width(&pair[0], &pair[1]) as f64 * 0.5 * widget.pixel_scale();
+			self.stroke_segment(to_widget * pair[0].pos, to_widget * pair[1].pos, radius, layer.color());
+		}
+	}
+
+	/// Draw a stroke on top from `first_sample` onwards, or everything again if it isn't the top one
+	fn draw_on_top(&mut self, index: usize, first_sample: usize) {
+		if index + 1 != self.stroke_count {
+			self.view = None;
+		} else if !self.pending.iter().any(|&(pending, _)| pending == index) {
+			self.pending.push((index, first_sample));
 		}
 	}
-	
-	pub fn end_stroke(&mut self) {
-		if let Some(progress) = self.current_stroke.take() {
-			if progress.events.len() < 2 {
-				return;
+
+	/// Draw a line with round caps, in widget coordinates
+	fn stroke_segment(&mut self, a: Point2, b: Point2, radius: f64, color: Color) {
+		let reach = radius + 1.0;
+		let first_col = (a.x.min(b.x) - reach).floor().max(0.0) as isize;
+		let last_col = (a.x.max(b.x) + reach).ceil().min(self.width as f64 - 1.0) as isize;
+		let first_row = (a.y.min(b.y) - reach).floor().max(0.0) as isize;
+		let last_row = (a.y.max(b.y) + reach).ceil().min(self.height as f64 - 1.0) as isize;
+		let alpha = color.a as f64 / 255.0;
+
+		for row in first_row..=last_row {
+			// Only the columns within reach of the part of the segment near this row, so long diagonal lines don't cost
+			// their whole bounding box
+			let center = row as f64 + 0.5;
+			let (mut t0, mut t1) = match b.y - a.y {
+				dy if dy.abs() < 1e-9 => (0.0, 1.0),
+				dy => ((center - reach - a.y) / dy, (center + reach - a.y) / dy),
+			};
+			if t0 > t1 {
+				std::mem::swap(&mut t0, &mut t1);
+			}
+			let (t0, t1) = (t0.max(0.0), t1.min(1.0));
+			if t0 > t1 {
+				continue;
+			}
+			let (x0, x1) = (a.x + (b.x - a.x) * t0, a.x + (b.x - a.x) * t1);
+			let row_first_col = first_col.max((x0.min(x1) - reach).floor() as isize);
+			let row_last_col = last_col.min((x0.max(x1) + reach).ceil() as isize);
+
+			for col in row_first_col..=row_last_col {
+				let distance = segment_distance(Point2::new(col as f64 + 0.5, row as f64 + 0.5), a, b);
+				// Overlap of the pixel's width with the stroke across it, which also keeps hairlines faint
+				let coverage = (radius.min(distance + 0.5) - (-radius).max(distance - 0.5)).clamp(0.0, 1.0);
+				if coverage <= 0.0 {
+					continue;
+				}
+
+				let offset = (row as usize * self.width as usize + col as usize) * 4;
+				let pixel = &mut self.pixels[offset..offset + 3];
+				for (channel, value) in pixel.iter_mut().zip([color.r, color.g, color.b]) {
+					*channel = blend(*channel, value, coverage * alpha);
+				}
 			}
-			
-			let path = pen_stroke_to_path(&progress.events, 4.0, &flat_pressure_curve);
-			let layer = self.composition.get_mut(Order::new(self.next_order - 1).unwrap()).unwrap();
-			layer.insert(&path);
 		}
 	}
-	
-	pub fn width(&self) -> u32 { self.width }
-	
-	pub fn height(&self) -> u32 { self.height }
 }
 
-struct StrokeInProgress {
-	events: Vec<PenEvent>,
-}
+impl StrokeHooks for RasterCanvas {
+	fn reset_strokes(&mut self, layers: &[Layer]) {
+		self.stroke_count = layers.len();
+		self.view = None;
+	}
 
-impl StrokeInProgress {
-	pub fn new() -> Self {
-		Self {
-			events: Vec::new(),
-		}
+	fn insert_stroke(&mut self, index: usize, _layer: &Layer) {
+		self.stroke_count += 1;
+		self.draw_on_top(index, 0);
 	}
-	
-	pub fn move_to(&mut self, point: Vec2, pressure: f32) {
-		self.events.push(PenEvent {
-			pos: point,
-			pressure,
-			speed: 1.0,
-		});
+
+	fn remove_stroke(&mut self, _index: usize) {
+		self.stroke_count -= 1;
+		self.view = None;
 	}
-}
 
-fn segment_to_path(segment: [PenEvent; 2], width: f32, pressure_curve: &dyn Fn(f32) -> f32) -> Path {
-	let side = width / 2.0;
-	let extended = 0.0;
-	
-	let mut path_builder = PathBuilder::new();
-	let a = segment[0].pos;
-	let b = segment[1].pos;
-	let dir = b - a;
-	let perp = Vec2::new(dir.y, -dir.x).normalize();
-	path_builder.move_to(vec_to_point(a - dir * extended - perp * side * pressure_curve(segment[0].pressure)));
-	path_builder.line_to(vec_to_point(a - dir * extended + perp * side * pressure_curve(segment[0].pressure)));
-	path_builder.line_to(vec_to_point(b + dir * extended + perp * side * pressure_curve(segment[1].pressure)));
-	path_builder.line_to(vec_to_point(b + dir * extended - perp * side * pressure_curve(segment[1].pressure)));
-	path_builder.build()
-}
+	fn replace_stroke(&mut self, _index: usize, _layer: &Layer) {
+		self.view = None;
+	}
 
-fn pen_stroke_to_path(stroke: &[PenEvent], width: f32, pressure_curve: &dyn Fn(f32) -> f32) -> Path {
-	let side = width / 2.0;
-	
-	assert!(stroke.len() >= 2);
-	let mut path_builder = PathBuilder::new();
-	let a = stroke[0].pos;
-	let b = stroke[1].pos;
-	let dir = b - a;
-	let perp = Vec2::new(dir.y, -dir.x).normalize();
-	path_builder.move_to(vec_to_point(a - perp * side * pressure_curve(stroke[0].pressure)));
-	path_builder.line_to(vec_to_point(a + perp * side));
-	for i in 1..stroke.len() {
-		let a = stroke[i - 1].pos;
-		let b = stroke[i].pos;
-		let dir = b - a;
-		let perp = Vec2::new(dir.y, -dir.x).normalize();
-		path_builder.line_to(vec_to_point(b - perp * side * pressure_curve(stroke[i].pressure)));
-	}
-	let a = stroke[stroke.len() - 1].pos;
-	let b = stroke[stroke.len() - 2].pos;
-	let dir = b - a;
-	let perp = Vec2::new(dir.y, -dir.x).normalize();
-	path_builder.line_to(vec_to_point(b - perp * side * pressure_curve(stroke[stroke.len() - 2].pressure)));
-	for i in (0..(stroke.len() - 1)).rev() {
-		let a = stroke[i + 1].pos;
-		let b = stroke[i].pos;
-		let dir = b - a;
-		let perp = Vec2::new(dir.y, -dir.x).normalize();
-		path_builder.line_to(vec_to_point(b - perp * side * pressure_curve(stroke[i].pressure)));
-	}
-	path_builder.build()
-}
+	fn push_stroke(&mut self, index: usize, _layer: &Layer) {
+		self.draw_on_top(index, self.drawn_samples);
+	}
 
-fn point_to_vec(point: Point) -> Vec2 {
-	Vec2::new(point.x, point.y)
+	fn end_stroke(&mut self, index: usize, _layer: &Layer) {
+		self.draw_on_top(index, self.drawn_samples);
+	}
 }
 
-fn vec_to_point(vec: Vec2) -> Point {
-	Point::new(vec.x, vec.y)
+fn blend(below: u8, above: u8, alpha: f64) -> u8 {
+	(below as f64 + (above as f64 - below as f64) * alpha).round() as u8
 }
 
-struct StrokeData {
-	// invariant: len >= 2
-	points: Vec<StrokePoint>,
-	segments: Vec<Segment>,
+/// Renders the canvas with a `RasterCanvas` and uploads the pixels to the output texture
+pub struct RasterBackend {
+	output: OutputTexture,
+	canvas: RasterCanvas,
 }
 
-impl StrokeData {
-	pub fn segment(&self, i: usize) -> [StrokePoint; 2] {
-		[self.points[i], self.points[i + 1]]
+impl RasterBackend {
+	pub fn new(graphics: &Graphics, width: u32, height: u32) -> Self {
+		Self {
+			output: OutputTexture::new(graphics, width, height),
+			canvas: RasterCanvas::new(width, height),
+		}
 	}
 }
 
-#[derive(Debug, Clone, Copy)]
-struct StrokePoint {
-	pos: Vec2,
-	thickness: f32,
-}
+impl StrokeHooks for RasterBackend {
+	fn reset_strokes(&mut self, layers: &[Layer]) {
+		self.canvas.reset_strokes(layers);
+	}
 
-#[derive(Debug, Clone, Copy)]
-struct Segment {
-	// negative = left, positive = right
-	bias: f32,
-}
+	fn insert_stroke(&mut self, index: usize, layer: &Layer) {
+		self.canvas.insert_stroke(index, layer);
+	}
 
-fn clean_stroke(stroke: StrokeData) {
-	loop {
-		// find the segment with two points closest together
-		let dense_i = (0..stroke.points.len()).min_by_key(|&i| ((stroke.segment(i)[1].pos - stroke.segment(i)[0].pos).norm_squared() * 100000.0) as u64).unwrap();
-		let dense_seg = stroke.segment(dense_i);
-		let norm = (dense_seg[1].pos - dense_seg[0].pos).norm();
-		if norm < dense_seg[0].thickness + dense_seg[1].thickness {
-			break;
-		}
+	fn remove_stroke(&mut self, index: usize) {
+		self.canvas.remove_stroke(index);
+	}
+
+	fn replace_stroke(&mut self, index: usize, layer: &Layer) {
+		self.canvas.replace_stroke(index, layer);
+	}
+
+	fn push_stroke(&mut self, index: usize, layer: &Layer) {
+		self.canvas.push_stroke(index, layer);
+	}
+
+	fn end_stroke(&mut self, index: usize, layer: &Layer) {
+		self.canvas.end_stroke(index, layer);
 	}
 }
 
-fn stroke_data_to_path(stroke_data: &StrokeData) {
-	
-}
\ No newline at end of file
+impl CanvasBackend for RasterBackend {
+	fn kind(&self) -> BackendKind {
+		BackendKind::Raster
+	}
+
+	fn resize(&mut self, graphics: &Graphics, width: u32, height: u32) {
+		self.output = OutputTexture::new(graphics, width, height);
+		self.canvas.resize(width, height);
+	}
+
+	fn render(&mut self, graphics: &Graphics, widget: &CanvasWidget) -> anyhow::Result<()> {
+		self.canvas.update(widget);
+
+		let (width, height) = (self.output.get_width(), self.output.get_height());
+		graphics.queue.write_texture(
+			self.output.get_texture().as_image_copy(),
+			self.canvas.pixels(),
+			wgpu::ImageDataLayout {
+				offset: 0,
+				bytes_per_row: std::num::NonZeroU32::new(width * 4),
+				rows_per_image: None,
+			},
+			wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+		);
+		Ok(())
+	}
+
+	fn output(&self) -> &OutputTexture {
+		&self.output
+	}
+}
@@ -1,237 +1,483 @@
-use linalg::Vec2;
-use lyon::{lyon_tessellation::{StrokeTessellator, StrokeOptions, VertexBuffers, geometry_builder::simple_builder}, path::{LineCap, LineJoin, traits::{PathBuilder, Build}}, math::Point};
-use wgpu::{Texture, TextureView, Buffer, RenderPipeline, util::DeviceExt};
+use linalg::prelude::*;
+use lyon::{
+	lyon_tessellation::{BuffersBuilder, StrokeOptions, StrokeTessellator, StrokeVertex, VertexBuffers},
+	math::Point,
+	path::{
+		traits::{Build, PathBuilder},
+		LineCap, LineJoin,
+	},
+};
+use wgpu::{BindGroup, BindGroupLayout, Buffer, BufferAddress, BufferUsages, RenderPipeline, TextureView};
 
-use crate::{Graphics, pen::{PenEvent, flat_pressure_curve}};
+use super::backend::{BackendKind, CanvasBackend, OutputTexture, StrokeHooks};
+use super::chunk::chunk_origin;
+use super::{CanvasWidget, Layer};
+use crate::pen::{flat_pressure_curve, STROKE_WIDTH};
+use crate::util::timeit;
+use crate::Graphics;
 
-#[derive(Debug)]
-pub struct Layer {
-	vertex: Buffer,
-	index: Buffer,
-	len: u32,
+/// Samples per pixel of the texture strokes are drawn into before it is resolved to the output
+const MSAA_SAMPLES: u32 = 4;
+
+/// Size in bytes the shared vertex and index buffers start out with
+const INITIAL_BUFFER_SIZE: BufferAddress = 1 << 16;
+
+/// Sides of the polygons the dots of a background pattern are drawn as
+const DOT_SIDES: usize = 12;
+
+/// A tessellated vertex, positioned relative to the origin of the chunk its stroke belongs to
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+	local: [f32; 2],
+	origin: [f32; 2],
+	color: [u8; 4],
+}
+
+/// Matches the `View` struct in `tri_canvas.wgsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ViewUniforms {
+	pan_chunk: [f32; 2],
+	pan_offset: [f32; 2],
+	/// Columns of the page to widget rotation and zoom
+	linear: [[f32; 2]; 2],
+	size: [f32; 2],
+	_padding: [f32; 2],
+}
+
+impl ViewUniforms {
+	fn new(widget: &CanvasWidget) -> Self {
+		// Chunk origins are multiples of the chunk size, so they and their differences are exact in f32
+		let pan_chunk = chunk_origin(Point2::from(widget.pan));
+		let pan_offset = widget.pan - pan_chunk;
+		let m = widget.inv_transform().matrix();
+		Self {
+			pan_chunk: [pan_chunk.x as f32, pan_chunk.y as f32],
+			pan_offset: [pan_offset.x as f32, pan_offset.y as f32],
+			linear: [[m[(0, 0)] as f32, m[(1, 0)] as f32], [m[(0, 1)] as f32, m[(1, 1)] as f32]],
+			size: [widget.get_width() as f32, widget.get_height() as f32],
+			_padding: [0.0; 2],
+		}
+	}
+}
+
+/// The triangles of one stroke, with indices counted from its first vertex
+#[derive(Debug, Default)]
+struct StrokeMesh {
+	vertices: Vec<Vertex>,
+	indices: Vec<u32>,
+}
+
+/// A GPU buffer that is reallocated at twice the size whenever its contents outgrow it
+struct GrowingBuffer {
+	label: &'static str,
+	usage: BufferUsages,
+	buffer: Buffer,
+	capacity: BufferAddress,
+}
+
+impl GrowingBuffer {
+	fn new(graphics: &Graphics, label: &'static str, usage: BufferUsages) -> Self {
+		let usage = usage | BufferUsages::COPY_DST;
+		Self {
+			label,
+			usage,
+			buffer: Self::allocate(graphics, label, usage, INITIAL_BUFFER_SIZE),
+			capacity: INITIAL_BUFFER_SIZE,
+		}
+	}
+
+	fn allocate(graphics: &Graphics, label: &'static str, usage: BufferUsages, size: BufferAddress) -> Buffer {
+		graphics.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some(label),
+			size,
+			usage,
+			mapped_at_creation: false,
+		})
+	}
+
+	/// Upload `contents`, of which the first `unchanged` bytes are already in the buffer
+	fn write(&mut self, graphics: &Graphics, contents: &[u8], unchanged: usize) {
+		let len = contents.len() as BufferAddress;
+		if len > self.capacity {
+			self.capacity = len.next_power_of_two();
+			self.buffer = Self::allocate(graphics, self.label, self.usage, self.capacity);
+			log::debug!("Grew {} to {} bytes", self.label, self.capacity);
+			graphics.queue.write_buffer(&self.buffer, 0, contents);
+		} else if unchanged < contents.len() {
+			graphics
+				.queue
+				.write_buffer(&self.buffer, unchanged as BufferAddress, &contents[unchanged..]);
+		}
+	}
+}
+
+fn as_bytes<T>(slice: &[T]) -> &[u8] {
+	unsafe { std::slice::from_raw_parts(slice.as_ptr() as *const u8, std::mem::size_of_val(slice)) }
 }
 
-pub struct Canvas {
-	width: u32,
-	height: u32,
-	output: Texture,
-	output_view: TextureView,
+/// Renders the canvas from strokes tessellated into triangles with lyon, which is light enough for weaker GPUs.
+///
+/// Each stroke is tessellated when the `StrokeHooks` pass it on, and only then. All strokes live in one vertex and one
+/// index buffer in drawing order and are drawn with a single call. Only the strokes from the first changed one onwards
+/// are copied into the buffers again, so drawing a stroke or undoing one only touches the end of the buffers.
+pub struct TriBackend {
+	output: OutputTexture,
+	msaa_view: TextureView,
 	pipeline: RenderPipeline,
-	todo_layers: Vec<VertexBuffers<Point, u16>>,
-	layers: Vec<Layer>,
-	current_stroke: Option<Vec<PenEvent>>,
+	/// Kept to build the pipeline again when the shader is hot reloaded
+	bind_group_layout: BindGroupLayout,
+	view_uniforms: Buffer,
+	bind_group: BindGroup,
+	vertex_buffer: GrowingBuffer,
+	index_buffer: GrowingBuffer,
+	/// The strokes in drawing order
+	strokes: Vec<StrokeMesh>,
+	/// The first stroke that changed since the buffers were written, if any did
+	first_changed: Option<usize>,
+	/// CPU copies of the buffer contents, with indices already offset to where their stroke's vertices start
+	vertices: Vec<Vertex>,
+	indices: Vec<u32>,
+	/// Where each stroke ends in `vertices` and `indices`. It starts where the previous one ends.
+	ranges: Vec<(usize, usize)>,
+	/// The background pattern, which follows the view and is rebuilt on every render
+	background_vertex_buffer: GrowingBuffer,
+	background_index_buffer: GrowingBuffer,
+	background_index_count: u32,
 }
 
-impl Canvas {
-	pub fn new(graphics: &Graphics, width: u32, height: u32) -> anyhow::Result<Self> {
-		let (output, output_view) = Self::create_texture(graphics, width, height);
-		let mut layers = Vec::new();
-		
-		let shader = graphics.device.create_shader_module(wgpu::include_wgsl!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/tri_canvas.wgsl")));
+impl TriBackend {
+	pub fn new(graphics: &Graphics, width: u32, height: u32) -> Self {
+		let bind_group_layout = graphics.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Tri Canvas Bind Group Layout"),
+			entries: &[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::VERTEX,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: None,
+				},
+				count: None,
+			}],
+		});
+		let view_uniforms = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Tri Canvas View Buffer"),
+			size: std::mem::size_of::<ViewUniforms>() as BufferAddress,
+			usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+		let bind_group = graphics.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Tri Canvas Bind Group"),
+			layout: &bind_group_layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: view_uniforms.as_entire_binding(),
+			}],
+		});
+
+		let pipeline = Self::create_pipeline(graphics, &bind_group_layout);
+
+		Self {
+			output: OutputTexture::new(graphics, width, height),
+			msaa_view: Self::create_msaa_view(graphics, width, height),
+			pipeline,
+			bind_group_layout,
+			view_uniforms,
+			bind_group,
+			vertex_buffer: GrowingBuffer::new(graphics, "Tri Canvas Vertex Buffer", BufferUsages::VERTEX),
+			index_buffer: GrowingBuffer::new(graphics, "Tri Canvas Index Buffer", BufferUsages::INDEX),
+			strokes: Vec::new(),
+			first_changed: None,
+			vertices: Vec::new(),
+			indices: Vec::new(),
+			ranges: Vec::new(),
+			background_vertex_buffer: GrowingBuffer::new(graphics, "Tri Canvas Background Vertex Buffer", BufferUsages::VERTEX),
+			background_index_buffer: GrowingBuffer::new(graphics, "Tri Canvas Background Index Buffer", BufferUsages::INDEX),
+			background_index_count: 0,
+		}
+	}
+
+	/// Build the render pipeline from the current `tri_canvas.wgsl`
+	fn create_pipeline(graphics: &Graphics, bind_group_layout: &BindGroupLayout) -> RenderPipeline {
+		let shader = crate::shader::load(&graphics.device, "tri_canvas.wgsl");
 		let pipeline_layout = graphics.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
 			label: Some("Tri Canvas Pipeline Layout"),
-			bind_group_layouts: &[],
+			bind_group_layouts: &[bind_group_layout],
 			push_constant_ranges: &[],
 		});
-		let pipeline = graphics.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Tri Canvas Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[wgpu::VertexBufferLayout {
-					array_stride: std::mem::size_of::<Point>() as wgpu::BufferAddress,
+		graphics.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Tri Canvas Render Pipeline"),
+			layout: Some(&pipeline_layout),
+			vertex: wgpu::VertexState {
+				module: &shader,
+				entry_point: "vs_main",
+				buffers: &[wgpu::VertexBufferLayout {
+					array_stride: std::mem::size_of::<Vertex>() as BufferAddress,
 					step_mode: wgpu::VertexStepMode::Vertex,
-					attributes: &[
-						wgpu::VertexAttribute {
-							offset: 0,
-							shader_location: 0,
-							format: wgpu::VertexFormat::Float32x2,
-						},
-					],
+					attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Unorm8x4],
 				}],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-		
-		//let vertices = [Point::new(0.1, 0.1), Point::new(0.1, 0.5), Point::new(0.5, 0.5)];
-		let vertices = [0.1f32, 0.1, 0.1, 0.5, 0.5, 0.5];
-		let indices = [0u16, 1, 2];
-		let vertex = graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-			label: Some("Vertex Buffer"),
-			contents: unsafe { std::slice::from_raw_parts(vertices.as_ptr() as *const _, vertices.len() * std::mem::size_of::<f32>()) },
-			usage: wgpu::BufferUsages::VERTEX,
-		});
-		let index = graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-			label: Some("Index Buffer"),
-			contents: unsafe { std::slice::from_raw_parts(indices.as_ptr() as *const _, indices.len() * std::mem::size_of::<u16>()) },
-			usage: wgpu::BufferUsages::INDEX,
-		});
-		layers.push(Layer {
-			vertex,
-			index,
-			len: 3,
-		});
-		
-		
-		Ok(Self {
-			width,
-			height,
-			output,
-			output_view,
-			pipeline,
-			todo_layers: Vec::new(),
-			layers,
-			current_stroke: None,
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: &shader,
+				entry_point: "fs_main",
+				targets: &[Some(wgpu::ColorTargetState {
+					format: wgpu::TextureFormat::Rgba8Unorm,
+					blend: Some(wgpu::BlendState {
+						color: wgpu::BlendComponent::REPLACE,
+						alpha: wgpu::BlendComponent::REPLACE,
+					}),
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::TriangleList,
+				strip_index_format: None,
+				front_face: wgpu::FrontFace::Ccw,
+				cull_mode: None,
+				polygon_mode: wgpu::PolygonMode::Fill,
+				unclipped_depth: false,
+				conservative: false,
+			},
+			depth_stencil: None,
+			multisample: wgpu::MultisampleState {
+				count: MSAA_SAMPLES,
+				mask: !0,
+				alpha_to_coverage_enabled: false,
+			},
+			multiview: None,
 		})
 	}
-	
-	fn create_texture(graphics: &Graphics, width: u32, height: u32) -> (Texture, TextureView) {
+
+	fn create_msaa_view(graphics: &Graphics, width: u32, height: u32) -> TextureView {
 		let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
-			label: Some("Canvas Output"),
-			size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+			label: Some("Tri Canvas MSAA Texture"),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
 			mip_level_count: 1,
-			sample_count: 1,
+			sample_count: MSAA_SAMPLES,
 			dimension: wgpu::TextureDimension::D2,
-			format: wgpu::TextureFormat::Bgra8UnormSrgb,
-			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+			format: wgpu::TextureFormat::Rgba8Unorm,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
 		});
-		let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
-			label: Some("Canvas Output View"),
-			format: Some(wgpu::TextureFormat::Bgra8UnormSrgb),
-			dimension: Some(wgpu::TextureViewDimension::D2),
-			aspect: wgpu::TextureAspect::All,
-			base_mip_level: 0,
-			mip_level_count: None,
-			base_array_layer: 0,
-			array_layer_count: None,
+		texture.create_view(&wgpu::TextureViewDescriptor::default())
+	}
+
+	/// Tessellate a stroke in the coordinates of its chunk
+	fn tessellate(layer: &Layer) -> StrokeMesh {
+		let mut buffers = VertexBuffers::<Vertex, u32>::new();
+		let events = layer.events();
+		if events.is_empty() {
+			return StrokeMesh::default();
+		}
+
+		let origin = layer.origin();
+		let origin = [origin.x as f32, origin.y as f32];
+		let color = layer.color();
+		let color = [color.r, color.g, color.b, color.a];
+		let mut vertex_builder = BuffersBuilder::new(&mut buffers, |vertex: StrokeVertex| Vertex {
+			local: vertex.position().to_array(),
+			origin,
+			color,
 		});
-		(texture, texture_view)
+		let mut tessellator = StrokeTessellator::new();
+		let mut options = StrokeOptions::default();
+		options.start_cap = LineCap::Round;
+		options.end_cap = LineCap::Round;
+		options.line_join = LineJoin::Round;
+		options.line_width = 1.0;
+		options.variable_line_width = Some(0);
+		options.tolerance = 0.25;
+		let mut builder = tessellator.builder_with_attributes(1, &options, &mut vertex_builder);
+
+		let point = |i: usize| Point::new(events[i].pos.x as f32, events[i].pos.y as f32);
+		let width = |i: usize| [STROKE_WIDTH * flat_pressure_curve(events[i].pressure)];
+		builder.begin(point(0), &width(0));
+		for i in 1..events.len() {
+			builder.line_to(point(i), &width(i));
+		}
+		builder.end(false);
+		if let Err(e) = builder.build() {
+			log::warn!("Failed to tessellate stroke: {e:?}");
+			return StrokeMesh::default();
+		}
+
+		StrokeMesh {
+			vertices: buffers.vertices,
+			indices: buffers.indices,
+		}
 	}
-	
-	pub fn get_output(&self) -> &Texture {
-		&self.output
+
+	fn mark_changed(&mut self, index: usize) {
+		self.first_changed = Some(self.first_changed.map_or(index, |first| first.min(index)));
+	}
+
+	/// Build triangles for the background pattern in view, relative to the chunk of the pan like `ViewUniforms` expects
+	fn tessellate_background(widget: &CanvasWidget) -> (Vec<Vertex>, Vec<u32>) {
+		let pan_chunk = chunk_origin(Point2::from(widget.pan));
+		let origin = [pan_chunk.x as f32, pan_chunk.y as f32];
+		let to_page = widget.transform();
+		let mut vertices = Vec::new();
+		let mut indices = Vec::new();
+
+		for marks in widget.background.marks(widget) {
+			let color = [marks.color.r, marks.color.g, marks.color.b, marks.color.a];
+			// Marks are in widget coordinates, and their page coordinates are only a chunk or so away from `pan_chunk`
+			let mut polygon = |points: &[Point2]| {
+				let base = vertices.len() as u32;
+				vertices.extend(points.iter().map(|&point| {
+					let local = to_page * point - pan_chunk;
+					Vertex {
+						local: [local.x as f32, local.y as f32],
+						origin,
+						color,
+					}
+				}));
+				indices.extend((1..points.len() as u32 - 1).flat_map(|i| [base, base + i, base + i + 1]));
+			};
+
+			for &(from, to) in &marks.lines {
+				let direction = to - from;
+				if direction.norm() > 0.0 {
+					let normal = Vec2::new(-direction.y, direction.x).normalize() * (marks.line_width * 0.5);
+					polygon(&[from - normal, to - normal, to + normal, from + normal]);
+				}
+			}
+			for &center in &marks.dots {
+				let points: Vec<Point2> = (0..DOT_SIDES)
+					.map(|i| {
+						let angle = i as f64 / DOT_SIDES as f64 * std::f64::consts::TAU;
+						center + Vec2::new(angle.cos(), angle.sin()) * marks.dot_radius
+					})
+					.collect();
+				polygon(&points);
+			}
+		}
+		(vertices, indices)
 	}
-	
-	pub fn resize(&mut self, graphics: &Graphics, new_width: u32, new_height: u32) {
-		let (new_texture, new_texture_view) = Self::create_texture(graphics, new_width, new_height);
-		self.output = new_texture;
-		self.output_view = new_texture_view;
-		self.width = new_width;
-		self.height = new_height;
-		self.render(graphics);
-	}
-	
-	pub fn render(&mut self, graphics: &Graphics) {
-		for todo_layer in std::mem::take(&mut self.todo_layers) {
-			let vertex = graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-				label: Some("Vertex Buffer"),
-				contents: unsafe { std::slice::from_raw_parts(todo_layer.vertices.as_ptr() as *const _, todo_layer.vertices.len() * std::mem::size_of::<Point>()) },
-				usage: wgpu::BufferUsages::VERTEX,
-			});
-			let index = graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-				label: Some("Index Buffer"),
-				contents: unsafe { std::slice::from_raw_parts(todo_layer.indices.as_ptr() as *const _, todo_layer.indices.len() * std::mem::size_of::<u16>()) },
-				usage: wgpu::BufferUsages::INDEX,
-			});
-			self.layers.push(Layer {
-				vertex,
-				index,
-				len: todo_layer.indices.len().try_into().unwrap(),
-			})
+
+	/// Copy the strokes from the first changed one onwards into the shared buffers
+	fn update_buffers(&mut self, graphics: &Graphics) {
+		let first = match self.first_changed.take() {
+			Some(first) => first,
+			None => return,
+		};
+
+		let (vertex_start, index_start) = match first {
+			0 => (0, 0),
+			_ => self.ranges[first - 1],
+		};
+		self.vertices.truncate(vertex_start);
+		self.indices.truncate(index_start);
+		self.ranges.truncate(first);
+		for stroke in &self.strokes[first..] {
+			let base = self.vertices.len() as u32;
+			self.vertices.extend_from_slice(&stroke.vertices);
+			self.indices.extend(stroke.indices.iter().map(|index| index + base));
+			self.ranges.push((self.vertices.len(), self.indices.len()));
 		}
-		
-		let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Tri Canvas Render Encoder") });
+
+		self.vertex_buffer.write(
+			graphics,
+			as_bytes(&self.vertices),
+			vertex_start * std::mem::size_of::<Vertex>(),
+		);
+		self.index_buffer
+			.write(graphics, as_bytes(&self.indices), index_start * std::mem::size_of::<u32>());
+	}
+}
+
+impl StrokeHooks for TriBackend {
+	fn reset_strokes(&mut self, layers: &[Layer]) {
+		self.strokes = timeit!("tessellate strokes", layers.iter().map(Self::tessellate).collect());
+		self.first_changed = Some(0);
+	}
+
+	fn insert_stroke(&mut self, index: usize, layer: &Layer) {
+		self.strokes.insert(index, Self::tessellate(layer));
+		self.mark_changed(index);
+	}
+
+	fn remove_stroke(&mut self, index: usize) {
+		self.strokes.remove(index);
+		self.mark_changed(index);
+	}
+
+	fn replace_stroke(&mut self, index: usize, layer: &Layer) {
+		self.strokes[index] = Self::tessellate(layer);
+		self.mark_changed(index);
+	}
+}
+
+impl CanvasBackend for TriBackend {
+	fn kind(&self) -> BackendKind {
+		BackendKind::Tri
+	}
+
+	fn resize(&mut self, graphics: &Graphics, width: u32, height: u32) {
+		self.output = OutputTexture::new(graphics, width, height);
+		self.msaa_view = Self::create_msaa_view(graphics, width, height);
+	}
+
+	fn render(&mut self, graphics: &Graphics, widget: &CanvasWidget) -> anyhow::Result<()> {
+		self.update_buffers(graphics);
+		let (background_vertices, background_indices) = Self::tessellate_background(widget);
+		self.background_vertex_buffer
+			.write(graphics, as_bytes(&background_vertices), 0);
+		self.background_index_buffer.write(graphics, as_bytes(&background_indices), 0);
+		self.background_index_count = background_indices.len() as u32;
+		let view = ViewUniforms::new(widget);
+		graphics
+			.queue
+			.write_buffer(&self.view_uniforms, 0, as_bytes(std::slice::from_ref(&view)));
+
+		let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+			label: Some("Tri Canvas Render Encoder"),
+		});
 		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
 			label: Some("Tri Canvas Render Pass"),
 			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-				view: &self.output_view,
-				resolve_target: None,
+				view: &self.msaa_view,
+				resolve_target: Some(self.output.get_texture_view()),
 				ops: wgpu::Operations {
-					load: wgpu::LoadOp::Clear(wgpu::Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 }),
-					store: true,
+					load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+					// Only the resolved output is needed afterwards
+					store: false,
 				},
 			})],
 			depth_stencil_attachment: None,
 		});
 		render_pass.set_pipeline(&self.pipeline);
-		for layer in &self.layers {
-			render_pass.set_vertex_buffer(0, layer.vertex.slice(..));
-			render_pass.set_index_buffer(layer.index.slice(..), wgpu::IndexFormat::Uint16);
-			render_pass.draw_indexed(0..layer.len, 0, 0..1);
+		render_pass.set_bind_group(0, &self.bind_group, &[]);
+		if self.background_index_count > 0 {
+			render_pass.set_vertex_buffer(0, self.background_vertex_buffer.buffer.slice(..));
+			render_pass.set_index_buffer(self.background_index_buffer.buffer.slice(..), wgpu::IndexFormat::Uint32);
+			render_pass.draw_indexed(0..self.background_index_count, 0, 0..1);
+		}
+		if !self.indices.is_empty() {
+			render_pass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
+			render_pass.set_index_buffer(self.index_buffer.buffer.slice(..), wgpu::IndexFormat::Uint32);
+			render_pass.draw_indexed(0..self.indices.len() as u32, 0, 0..1);
 		}
 		drop(render_pass);
-		let commands = encoder.finish();
-		graphics.queue.submit([commands]);
+		timeit!("render canvas", graphics.queue.submit(Some(encoder.finish())));
+		Ok(())
 	}
-	
-	pub fn start_stroke(&mut self) {
-		self.current_stroke = Some(Vec::new());
-	}
-	
-	pub fn move_stroke(&mut self, point: Vec2, pressure: f32) {
-		if let Some(ref mut current_stroke) = self.current_stroke {
-			current_stroke.push(PenEvent { pos: point, pressure, speed: 1.0 });
-		}
+
+	fn output(&self) -> &OutputTexture {
+		&self.output
 	}
-	
-	pub fn end_stroke(&mut self) {
-		if let Some(current_stroke) = self.current_stroke.take() {
-			if current_stroke.len() < 1 {
-				return;
-			}
-			
-			let mut buffers = VertexBuffers::<Point, u16>::new();
-			let mut vertex_builder = simple_builder(&mut buffers);
-			let mut tesselator = StrokeTessellator::new();
-			let mut options = StrokeOptions::default();
-			options.start_cap = LineCap::Round;
-			options.end_cap = LineCap::Round;
-			options.line_join = LineJoin::Round;
-			options.line_width = 4.0;
-			options.variable_line_width = Some(0);
-			options.miter_limit = StrokeOptions::DEFAULT_MITER_LIMIT;
-			options.tolerance = 1.0;
-			let mut builder = tesselator.builder_with_attributes(1, &options, &mut vertex_builder);
-			
-			builder.begin(Point::new(current_stroke[0].pos.x, current_stroke[0].pos.y), &[flat_pressure_curve(current_stroke[0].pressure)]);
-			for &event in &current_stroke[1..] {
-				builder.line_to(Point::new(event.pos.x, event.pos.y), &[flat_pressure_curve(event.pressure)]);
-			}
-			builder.build().unwrap();
-			self.todo_layers.push(buffers);
+
+	fn reload_shaders(&mut self, graphics: &Graphics) {
+		graphics.device.push_error_scope(wgpu::ErrorFilter::Validation);
+		let pipeline = Self::create_pipeline(graphics, &self.bind_group_layout);
+		match pollster::block_on(graphics.device.pop_error_scope()) {
+			Some(e) => log::error!("Keeping the previous tri canvas pipeline, the new shader failed to compile: {e}"),
+			None => self.pipeline = pipeline,
 		}
 	}
-	
-	pub fn width(&self) -> u32 { self.width }
-	
-	pub fn height(&self) -> u32 { self.height }
 }
-
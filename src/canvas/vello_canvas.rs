@@ -1,70 +1,457 @@
-use linalg::na::{Affine2, Scale2, Translation2};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use linalg::na::{Affine2, Rotation2, Scale2, Translation2};
 use linalg::prelude::*;
-use vello::kurbo::{Affine, Line, Point, Rect};
+use vello::kurbo::{Affine, BezPath, Circle, Line, Point, Rect, Shape};
 use vello::peniko::{Brush, Cap, Color, Fill, Join, Stroke};
 use vello::{FragmentBuilder, Renderer, Scene, SceneFragment};
-use wgpu::{Texture, TextureView};
 
-use crate::pen::flat_pressure_curve;
+use super::backend::{BackendKind, CanvasBackend, OutputTexture, StrokeHooks};
+use super::background::Background;
+use super::chunk::{chunk_origin, local_to_widget};
+use super::lod::{self, LOD_TOLERANCES};
+use crate::pen::{flat_pressure_curve, segment_width, STROKE_WIDTH};
 use crate::util::*;
 use crate::{pen::PenEvent, Graphics};
 
+/// Number of changes to its strokes a canvas remembers for renderers to catch up with
+const MAX_CHANGES: usize = 4096;
+
+/// Source of `Canvas::id`
+static NEXT_CANVAS_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A change to the strokes of a canvas. The index is into `Canvas::layers` right after the change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrokeChange {
+	/// A stroke was started on top of the others
+	Started(usize),
+	/// Samples were added to the stroke in progress
+	Pushed(usize),
+	/// The stroke in progress was finished
+	Ended(usize),
+	/// A finished stroke was inserted, e.g. by undoing an erase
+	Inserted(usize),
+	/// A stroke was removed. The index is where it was before.
+	Removed(usize),
+	/// A stroke was changed other than by adding samples, e.g. moved or reshaped
+	Replaced(usize),
+}
+
 pub struct Canvas {
-	layers: Vec<SceneFragment>,
+	/// Identifies the canvas, so renderers following its changes can tell it from another one
+	id: u64,
+	/// Color of new strokes
+	pub color: Color,
+	layers: Vec<Layer>,
+	/// Edits that can be undone, most recent last
+	undo_stack: Vec<Edit>,
+	/// Edits that can be redone, most recently undone last
+	redo_stack: Vec<Edit>,
 	active_stroke: Option<ActiveStroke>,
+	/// Layers removed by the erase in progress, in the order they were removed
+	pending_erase: Vec<(usize, Layer)>,
+	/// Indices of the selected layers, in ascending order
+	selection: Vec<usize>,
+	/// Total movement of the selection drag in progress
+	pending_move: Vec2,
+	/// The most recent changes to `layers`, oldest first
+	changes: VecDeque<StrokeChange>,
+	/// Number of changes made before the oldest one in `changes`
+	dropped_changes: u64,
+}
+
+/// A reversible change to the layers of a canvas
+enum Edit {
+	/// Layers were inserted at these indices, in this order
+	Inserted(Vec<usize>),
+	/// Layers were removed from these indices, in this order
+	Removed(Vec<(usize, Layer)>),
+	/// Layers at these indices were moved by an offset
+	Moved(Vec<usize>, Vec2),
 }
 
 impl Canvas {
 	pub fn new() -> Self {
-		let mut layers = Vec::new();
-
-		// Add white background as first layer
-		let mut builder = FragmentBuilder::new();
-		builder.fill(
-			Fill::NonZero,
-			Affine::IDENTITY,
-			&Brush::Solid(Color::rgb8(255, 255, 255)),
-			None,
-			&Rect {
-				x0: -100000.0,
-				y0: -100000.0,
-				x1: 100000.0,
-				y1: 100000.0,
-			},
-		);
-		layers.push(builder.finish());
-
 		Self {
-			layers,
+			id: NEXT_CANVAS_ID.fetch_add(1, Ordering::Relaxed),
+			color: Color::rgb8(0, 0, 0),
+			layers: Vec::new(),
+			undo_stack: Vec::new(),
+			redo_stack: Vec::new(),
 			active_stroke: None,
+			pending_erase: Vec::new(),
+			selection: Vec::new(),
+			pending_move: Vec2::zero(),
+			changes: VecDeque::new(),
+			dropped_changes: 0,
+		}
+	}
+
+	pub fn id(&self) -> u64 {
+		self.id
+	}
+
+	/// Get the number of changes made to the strokes so far
+	pub fn change_count(&self) -> u64 {
+		self.dropped_changes + self.changes.len() as u64
+	}
+
+	/// Get the changes made after the first `seen`, or `None` if some of them are no longer remembered
+	pub fn changes_since(&self, seen: u64) -> Option<impl Iterator<Item = StrokeChange> + '_> {
+		let skip = seen.checked_sub(self.dropped_changes)?;
+		(skip <= self.changes.len() as u64).then(|| self.changes.iter().copied().skip(skip as usize))
+	}
+
+	fn record(&mut self, change: StrokeChange) {
+		if self.changes.len() == MAX_CHANGES {
+			self.changes.pop_front();
+			self.dropped_changes += 1;
 		}
+		self.changes.push_back(change);
 	}
 
 	pub fn start_stroke(&mut self) {
+		self.layers.push(Layer::new(self.color));
 		self.active_stroke = Some(ActiveStroke::new());
-		self.layers.push(SceneFragment::new());
+		self.record(StrokeChange::Started(self.layers.len() - 1));
 	}
 
 	pub fn move_stroke(&mut self, point: Point2, pressure: f32) {
-		if let Some(ref mut active) = self.active_stroke {
-			active.push_event(PenEvent {
-				pos: point,
-				pressure,
-				speed: 1.0, // TODO
-			});
+		if self.push_point(point, pressure) {
+			self.record(StrokeChange::Pushed(self.layers.len() - 1));
+		}
+	}
 
-			self.layers.pop();
-			self.layers.push(active.get_fragment());
+	/// Add a sample to the stroke in progress, returning whether there is one. The sample is added to its layer in
+	/// place, so a stroke costs the same for every sample however long it gets.
+	fn push_point(&mut self, point: Point2, pressure: f32) -> bool {
+		match (&mut self.active_stroke, self.layers.last_mut()) {
+			(Some(active), Some(layer)) => {
+				layer.push_event(point, pressure);
+				active.encode(layer);
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Replace every point of the stroke in progress, e.g. to reshape a line while it is being dragged out
+	pub fn replace_stroke(&mut self, points: &[(Point2, f32)]) {
+		match (&mut self.active_stroke, self.layers.last_mut()) {
+			(Some(active), Some(layer)) => {
+				*active = ActiveStroke::new();
+				*layer = Layer::new(layer.color);
+				for &(point, pressure) in points {
+					layer.push_event(point, pressure);
+				}
+				active.encode(layer);
+			}
+			_ => return,
 		}
+		self.record(StrokeChange::Replaced(self.layers.len() - 1));
 	}
 
 	pub fn end_stroke(&mut self) {
-		self.active_stroke.take();
+		if let Some(active) = self.active_stroke.take() {
+			if let Some(layer) = self.layers.last_mut() {
+				layer.finish(active.finish());
+			}
+			self.push_edit(Edit::Inserted(vec![self.layers.len() - 1]));
+			self.record(StrokeChange::Ended(self.layers.len() - 1));
+		}
+	}
+
+	/// Discard the stroke in progress
+	pub fn cancel_stroke(&mut self) {
+		if self.active_stroke.take().is_some() {
+			self.layers.pop();
+			self.record(StrokeChange::Removed(self.layers.len()));
+		}
+	}
+
+	/// Remove every stroke passing within `radius` of a page point. The removals are collected into a single edit
+	/// until `commit_erase` is called. Returns whether anything was removed.
+	pub fn erase_at(&mut self, point: Point2, radius: f64) -> bool {
+		let mut erased = false;
+		let mut i = 0;
+		while i < self.layers.len() {
+			if self.layers[i].hit(point, radius) {
+				let layer = self.layers.remove(i);
+				self.pending_erase.push((i, layer));
+				self.record(StrokeChange::Removed(i));
+				erased = true;
+			} else {
+				i += 1;
+			}
+		}
+		if erased {
+			self.selection.clear();
+		}
+		erased
+	}
+
+	pub fn commit_erase(&mut self) {
+		let erased = std::mem::take(&mut self.pending_erase);
+		if !erased.is_empty() {
+			self.push_edit(Edit::Removed(erased));
+		}
+	}
+
+	/// Select every stroke lying entirely inside a page-space polygon. Returns whether anything was selected.
+	pub fn select_in_polygon(&mut self, polygon: &[Point2]) -> bool {
+		self.selection = (0..self.layers.len())
+			.filter(|&i| self.layers[i].inside_polygon(polygon))
+			.collect();
+		!self.selection.is_empty()
+	}
+
+	pub fn clear_selection(&mut self) {
+		self.selection.clear();
+	}
+
+	/// Check whether a page point lies within the bounds of a selected stroke
+	pub fn selection_contains(&self, point: Point2) -> bool {
+		self.selection
+			.iter()
+			.filter_map(|&i| self.layers[i].page_bounds())
+			.any(|bounds| bounds.contains(point.ltov()))
+	}
+
+	/// Get the page bounds of the whole selection, if anything is selected
+	pub fn selection_bounds(&self) -> Option<Rect> {
+		self.selection
+			.iter()
+			.filter_map(|&i| self.layers[i].page_bounds())
+			.reduce(|a, b| a.union(b))
+	}
+
+	/// Get the page position of the first sample of the first selected stroke, which moved selections snap by
+	pub fn selection_anchor(&self) -> Option<Point2> {
+		self.selection.iter().find_map(|&i| {
+			let layer = &self.layers[i];
+			layer.events.first().map(|event| event.pos + layer.origin)
+		})
+	}
+
+	/// Move the selected strokes. The movement is collected into a single edit until `commit_move` is called.
+	pub fn move_selection(&mut self, delta: Vec2) {
+		for k in 0..self.selection.len() {
+			let i = self.selection[k];
+			self.layers[i].origin += delta;
+			self.record(StrokeChange::Replaced(i));
+		}
+		self.pending_move += delta;
+	}
+
+	pub fn commit_move(&mut self) {
+		let delta = std::mem::replace(&mut self.pending_move, Vec2::zero());
+		if !self.selection.is_empty() && delta != Vec2::zero() {
+			let selection = self.selection.clone();
+			self.push_edit(Edit::Moved(selection, delta));
+		}
+	}
+
+	fn push_edit(&mut self, edit: Edit) {
+		self.undo_stack.push(edit);
+		self.redo_stack.clear();
+	}
+
+	/// Undo an edit, returning the edit that redoes it
+	fn revert(&mut self, edit: Edit) -> Edit {
+		match edit {
+			Edit::Inserted(indices) => Edit::Removed(
+				indices
+					.into_iter()
+					.rev()
+					.map(|i| {
+						self.record(StrokeChange::Removed(i));
+						(i, self.layers.remove(i))
+					})
+					.collect(),
+			),
+			Edit::Removed(removed) => Edit::Inserted(
+				removed
+					.into_iter()
+					.rev()
+					.map(|(i, layer)| {
+						self.layers.insert(i, layer);
+						self.record(StrokeChange::Inserted(i));
+						i
+					})
+					.collect(),
+			),
+			Edit::Moved(indices, delta) => {
+				for &i in &indices {
+					self.layers[i].origin -= delta;
+					self.record(StrokeChange::Replaced(i));
+				}
+				Edit::Moved(indices, -delta)
+			}
+		}
+	}
+
+	/// Get the page-space bounds of all strokes, or `None` if there are none
+	pub fn bounds(&self) -> Option<Rect> {
+		self.layers.iter().filter_map(Layer::page_bounds).reduce(|a, b| a.union(b))
+	}
+
+	/// Get the number of strokes on the canvas, including one being drawn
+	pub fn stroke_count(&self) -> usize {
+		self.layers.len()
+	}
+
+	/// Get the strokes in drawing order. The last one is the stroke being drawn, if there is one.
+	pub fn layers(&self) -> &[Layer] {
+		&self.layers
+	}
+
+	/// Undo the most recent edit. Returns whether there was one to undo.
+	pub fn undo(&mut self) -> bool {
+		if self.active_stroke.is_some() {
+			return false;
+		}
+		match self.undo_stack.pop() {
+			Some(edit) => {
+				self.selection.clear();
+				let redo = self.revert(edit);
+				self.redo_stack.push(redo);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Redo the most recently undone edit. Returns whether there was one to redo.
+	pub fn redo(&mut self) -> bool {
+		if self.active_stroke.is_some() {
+			return false;
+		}
+		match self.redo_stack.pop() {
+			Some(edit) => {
+				self.selection.clear();
+				let undo = self.revert(edit);
+				self.undo_stack.push(undo);
+				true
+			}
+			None => false,
+		}
 	}
 }
 
-pub struct ActiveStroke {
+/// A stroke on the canvas, with optional level-of-detail variants for rendering zoomed out
+pub struct Layer {
+	/// Page position of the chunk the samples and fragments are relative to, picked by the first sample so strokes
+	/// stay precise far from the page origin
+	origin: Vec2,
+	/// Stroke samples relative to `origin`
 	events: Vec<PenEvent>,
+	color: Color,
+	/// Bounds of the stroke including its width, relative to `origin`, or `None` before the first sample
+	bounds: Option<Rect>,
+	/// The whole stroke, or `None` while it is being drawn and the canvas's `ActiveStroke` holds it
+	full: Option<SceneFragment>,
+	/// One fragment per entry of `lod::LOD_TOLERANCES`, or empty if this layer has no simplified variants
+	lods: Vec<SceneFragment>,
+}
+
+impl Layer {
+	/// Start a stroke without any samples
+	fn new(color: Color) -> Self {
+		Self {
+			origin: Vec2::zero(),
+			events: Vec::new(),
+			color,
+			bounds: None,
+			full: None,
+			lods: Vec::new(),
+		}
+	}
+
+	/// Add a sample at a page point to a stroke being drawn
+	fn push_event(&mut self, point: Point2, pressure: f32) {
+		if self.events.is_empty() {
+			self.origin = chunk_origin(point);
+		}
+		let event = PenEvent {
+			pos: point - self.origin,
+			pressure,
+			speed: 1.0, // TODO
+		};
+		let sample = Rect::from_center_size(event.pos.ltov(), (STROKE_WIDTH as f64, STROKE_WIDTH as f64));
+		self.bounds = Some(self.bounds.map_or(sample, |bounds| bounds.union(sample)));
+		self.events.push(event);
+	}
+
+	/// Finish a stroke with the fragment encoded while it was drawn, adding simplified variants
+	fn finish(&mut self, full: SceneFragment) {
+		self.lods = LOD_TOLERANCES
+			.iter()
+			.map(|&tolerance| {
+				let mut builder = FragmentBuilder::new();
+				encode_stroke_segments(&mut builder, &lod::simplify(&self.events, tolerance), 1, self.color);
+				builder.finish()
+			})
+			.collect();
+		self.full = Some(full);
+	}
+
+	pub fn origin(&self) -> Vec2 {
+		self.origin
+	}
+
+	pub fn events(&self) -> &[PenEvent] {
+		&self.events
+	}
+
+	pub fn color(&self) -> Color {
+		self.color
+	}
+
+	/// Get the cheapest fragment that stays within a pixel of the full stroke at the given zoom, or `None` if the
+	/// stroke is still being drawn
+	pub fn fragment(&self, zoom: f64) -> Option<&SceneFragment> {
+		match lod::pick_lod(zoom) {
+			Some(i) if i < self.lods.len() => Some(&self.lods[i]),
+			_ => self.full.as_ref(),
+		}
+	}
+
+	/// Get the bounds of the stroke in page coordinates, or `None` if it has no samples yet
+	pub fn page_bounds(&self) -> Option<Rect> {
+		self.bounds.map(|bounds| bounds + self.origin.ltov().to_vec2())
+	}
+
+	/// Check whether the stroke passes within `radius` of a page point
+	pub fn hit(&self, point: Point2, radius: f64) -> bool {
+		let local = point - self.origin;
+		match self.bounds {
+			Some(bounds) if bounds.inflate(radius, radius).contains(local.ltov()) => {}
+			_ => return false,
+		}
+
+		let reach = |event: &PenEvent| radius + (STROKE_WIDTH * 0.5 * flat_pressure_curve(event.pressure)) as f64;
+		match self.events.as_slice() {
+			[event] => (local - event.pos).norm() <= reach(event),
+			events => events
+				.windows(2)
+				.any(|pair| segment_distance(local, pair[0].pos, pair[1].pos) <= reach(&pair[0]).max(reach(&pair[1]))),
+		}
+	}
+
+	/// Check whether every sample of the stroke lies inside a page-space polygon
+	pub fn inside_polygon(&self, polygon: &[Point2]) -> bool {
+		!self.events.is_empty()
+			&& self
+				.events
+				.iter()
+				.all(|event| point_in_polygon(event.pos + self.origin, polygon))
+	}
+}
+
+/// The fragment of the stroke being drawn, encoded a segment at a time as samples arrive
+pub struct ActiveStroke {
+	/// Index of the last sample whose segment is encoded
 	todo: usize,
 	builder: FragmentBuilder,
 }
@@ -72,47 +459,50 @@ pub struct ActiveStroke {
 impl ActiveStroke {
 	pub fn new() -> Self {
 		Self {
-			events: Vec::new(),
 			todo: 0,
 			builder: FragmentBuilder::new(),
 		}
 	}
 
-	pub fn push_event(&mut self, event: PenEvent) {
-		let width = 8.0;
-
-		let mut style = Stroke {
-			width: 0.0,
-			join: Join::Bevel,
-			miter_limit: 1.0,
-			start_cap: Cap::Round,
-			end_cap: Cap::Round,
-			dash_pattern: Default::default(),
-			dash_offset: 0.0,
-			scale: true,
-		};
-		let brush = Color {
-			r: 0,
-			g: 0,
-			b: 0,
-			a: 255,
-		};
-
-		self.events.push(event);
-		for i in (self.todo + 1)..self.events.len() {
-			let a = Point::new(self.events[i - 1].pos.x as f64, self.events[i - 1].pos.y as f64);
-			let b = Point::new(self.events[i].pos.x as f64, self.events[i].pos.y as f64);
-			style.width = width * flat_pressure_curve((self.events[i - 1].pressure + self.events[i].pressure) * 0.5);
-			self.builder.stroke(&style, Affine::IDENTITY, brush, None, &Line::new(a, b));
+	/// Encode the segments ending at the samples added to the layer since the last call
+	fn encode(&mut self, layer: &Layer) {
+		if let Some(last) = layer.events.len().checked_sub(1) {
+			encode_stroke_segments(&mut self.builder, &layer.events, self.todo + 1, layer.color);
+			self.todo = last;
 		}
-		self.todo = self.events.len() - 1;
 	}
 
 	pub fn get_fragment(&self) -> SceneFragment {
 		self.builder.clone().finish()
 	}
+
+	fn finish(self) -> SceneFragment {
+		self.builder.finish()
+	}
+}
+
+/// Encode the segments of a stroke ending at each event from `start` onwards
+fn encode_stroke_segments(builder: &mut FragmentBuilder, events: &[PenEvent], start: usize, color: Color) {
+	let mut style = Stroke {
+		width: 0.0,
+		join: Join::Bevel,
+		miter_limit: 1.0,
+		start_cap: Cap::Round,
+		end_cap: Cap::Round,
+		dash_pattern: Default::default(),
+		dash_offset: 0.0,
+		scale: true,
+	};
+	for i in start.max(1)..events.len() {
+		let a = Point::new(events[i - 1].pos.x as f64, events[i - 1].pos.y as f64);
+		let b = Point::new(events[i].pos.x as f64, events[i].pos.y as f64);
+		style.width = segment_width(&events[i - 1], &events[i]);
+		builder.stroke(&style, Affine::IDENTITY, color, None, &Line::new(a, b));
+	}
 }
 
+/// A canvas together with the view of it shown in a widget. It has no GPU resources, so input can be handled without
+/// a display. See `CanvasBackend` for drawing it.
 pub struct CanvasWidget {
 	pub canvas: Canvas,
 	width: u32,
@@ -120,72 +510,42 @@ pub struct CanvasWidget {
 	/// The displacement of the view of the widget from the origin of the page
 	/// Dragging left = panning right
 	pub pan: Vec2,
+	/// Logical pixels per page unit
 	pub zoom: f64,
-	renderer: Renderer,
-	target: Texture,
-	target_view: TextureView,
+	/// Physical pixels per logical pixel of the display the widget is on. Widget coordinates are physical pixels,
+	/// so strokes keep their size in logical pixels on any display.
+	scale_factor: f64,
+	/// The rotation in radians of the page relative to the widget
+	pub rotation: f64,
+	/// A page-space path drawn over the canvas, such as the outline of a lasso in progress
+	pub guide: Vec<Point2>,
+	/// The pattern drawn on the page under the strokes
+	pub background: Background,
+	/// Whether tools placing exact points snap them to the intersections of the background pattern
+	pub snap_to_grid: bool,
 }
 
 impl CanvasWidget {
-	pub fn new(graphics: &Graphics, width: u32, height: u32) -> anyhow::Result<Self> {
-		let renderer = Renderer::new(&graphics.device).map_err(|e| anyhow::format_err!("{e}"))?;
-		let canvas = Canvas::new();
-		let (target, target_view) = Self::create_texture(graphics, width, height);
-
-		Ok(Self {
-			canvas,
+	pub fn new(width: u32, height: u32) -> Self {
+		Self {
+			canvas: Canvas::new(),
 			width,
 			height,
 			pan: Vec2::zero(),
 			zoom: 1.0,
-			renderer,
-			target,
-			target_view,
-		})
+			scale_factor: 1.0,
+			rotation: 0.0,
+			guide: Vec::new(),
+			background: Background::default(),
+			snap_to_grid: false,
+		}
 	}
 
-	pub fn resize(&mut self, graphics: &Graphics, new_width: u32, new_height: u32) {
-		let (new_target, new_target_view) = Self::create_texture(graphics, new_width, new_height);
-		self.target = new_target;
-		self.target_view = new_target_view;
+	pub fn resize(&mut self, new_width: u32, new_height: u32) {
 		self.width = new_width;
 		self.height = new_height;
 	}
 
-	fn create_texture(graphics: &Graphics, width: u32, height: u32) -> (Texture, TextureView) {
-		let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
-			label: Some("Canvas Widget Target Texture"),
-			size: wgpu::Extent3d {
-				width,
-				height,
-				depth_or_array_layers: 1,
-			},
-			mip_level_count: 1,
-			sample_count: 1,
-			dimension: wgpu::TextureDimension::D2,
-			format: wgpu::TextureFormat::Rgba8Unorm,
-			usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-				| wgpu::TextureUsages::COPY_SRC
-				| wgpu::TextureUsages::STORAGE_BINDING
-				| wgpu::TextureUsages::TEXTURE_BINDING,
-		});
-		let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
-			label: Some("Canvas Widget Target TextureView"),
-			format: Some(wgpu::TextureFormat::Rgba8Unorm),
-			dimension: Some(wgpu::TextureViewDimension::D2),
-			aspect: wgpu::TextureAspect::All,
-			base_mip_level: 0,
-			mip_level_count: None,
-			base_array_layer: 0,
-			array_layer_count: None,
-		});
-		(texture, texture_view)
-	}
-
-	pub fn get_texture_view(&self) -> &TextureView {
-		&self.target_view
-	}
-
 	pub fn get_width(&self) -> u32 {
 		self.width
 	}
@@ -194,37 +554,304 @@ impl CanvasWidget {
 		self.height
 	}
 
+	pub fn scale_factor(&self) -> f64 {
+		self.scale_factor
+	}
+
+	/// Move the widget to a display with a different scale factor. The widget should be resized to match.
+	pub fn set_scale_factor(&mut self, scale_factor: f64) {
+		self.scale_factor = scale_factor;
+	}
+
+	/// Get how many widget pixels one page unit spans
+	pub fn pixel_scale(&self) -> f64 {
+		self.zoom * self.scale_factor
+	}
+
 	/// Transform widget coordinates to page coordinates
 	pub fn transform(&self) -> Affine2<f64> {
+		let scale = 1.0 / self.pixel_scale();
 		Affine2::from_matrix_unchecked(
 			Translation2::new(self.pan.x, self.pan.y).to_homogeneous()
-				* Scale2::new(1.0 / self.zoom, 1.0 / self.zoom).to_homogeneous(),
+				* Rotation2::new(self.rotation).to_homogeneous()
+				* Scale2::new(scale, scale).to_homogeneous(),
 		)
 	}
 
-	/// Transform page coordinates to widget coordinates according to internal offset and zoom
+	/// Transform page coordinates to widget coordinates according to internal offset, rotation and zoom
 	pub fn inv_transform(&self) -> Affine2<f64> {
 		self.transform().inverse()
 	}
 
-	pub fn render(&mut self, graphics: &Graphics) {
+	/// Snap a page point to the nearest intersection of the background pattern, if `snap_to_grid` is on and the
+	/// pattern has any
+	pub fn snap(&self, point: Point2) -> Point2 {
+		if !self.snap_to_grid {
+			return point;
+		}
+		self.background.nearest_intersection(self, point).unwrap_or(point)
+	}
+
+	/// Set the zoom while keeping the page point under `anchor` (in widget coordinates) in place
+	pub fn zoom_around(&mut self, anchor: Point2, zoom: f64) {
+		let before = self.transform() * anchor;
+		self.zoom = zoom;
+		let after = self.transform() * anchor;
+		self.pan += before - after;
+	}
+
+	/// Set the rotation while keeping the page point under `anchor` (in widget coordinates) in place
+	pub fn rotate_around(&mut self, anchor: Point2, rotation: f64) {
+		let before = self.transform() * anchor;
+		self.rotation = rotation;
+		let after = self.transform() * anchor;
+		self.pan += before - after;
+	}
+
+	/// Get the center of the widget in widget coordinates
+	pub fn center(&self) -> Point2 {
+		Point2::new(self.width as f64 * 0.5, self.height as f64 * 0.5)
+	}
+
+	/// Transform the chunk-local coordinates of a layer to widget coordinates
+	pub fn layer_transform(&self, origin: Vec2) -> Affine2<f64> {
+		local_to_widget(origin, self.pan, self.pixel_scale(), self.rotation)
+	}
+
+	/// Build a white background covering the whole widget with the pattern of the page on it, so it is visible at any pan
+	fn background(&self) -> SceneFragment {
+		let mut builder = FragmentBuilder::new();
+		builder.fill(
+			Fill::NonZero,
+			Affine::IDENTITY,
+			&Brush::Solid(Color::rgb8(255, 255, 255)),
+			None,
+			&Rect {
+				x0: 0.0,
+				y0: 0.0,
+				x1: self.width as f64,
+				y1: self.height as f64,
+			},
+		);
+
+		for marks in self.background.marks(self) {
+			let mut lines = BezPath::new();
+			for &(from, to) in &marks.lines {
+				lines.move_to(from.ltov());
+				lines.line_to(to.ltov());
+			}
+			let style = Stroke {
+				width: marks.line_width as f32,
+				join: Join::Miter,
+				miter_limit: 4.0,
+				start_cap: Cap::Butt,
+				end_cap: Cap::Butt,
+				dash_pattern: Default::default(),
+				dash_offset: 0.0,
+				scale: false,
+			};
+			if !marks.lines.is_empty() {
+				builder.stroke(&style, Affine::IDENTITY, marks.color, None, &lines);
+			}
+
+			if !marks.dots.is_empty() {
+				let mut dots = BezPath::new();
+				for &center in &marks.dots {
+					dots.extend(Circle::new(center.ltov(), marks.dot_radius).path_elements(0.1));
+				}
+				builder.fill(Fill::NonZero, Affine::IDENTITY, marks.color, None, &dots);
+			}
+		}
+		builder.finish()
+	}
+
+	/// Build the outlines drawn over the strokes, i.e. the selection bounds and the guide path
+	fn outlines(&self) -> SceneFragment {
+		let to_widget = self.inv_transform();
+		let mut path = BezPath::new();
+		let mut add_polyline = |points: &mut dyn Iterator<Item = Point2>, close: bool| {
+			for (i, point) in points.enumerate() {
+				let point = (to_widget * point).ltov();
+				if i == 0 {
+					path.move_to(point);
+				} else {
+					path.line_to(point);
+				}
+			}
+			if close {
+				path.close_path();
+			}
+		};
+
+		if let Some(bounds) = self.canvas.selection_bounds() {
+			let corners = [
+				(bounds.x0, bounds.y0),
+				(bounds.x1, bounds.y0),
+				(bounds.x1, bounds.y1),
+				(bounds.x0, bounds.y1),
+			];
+			add_polyline(&mut corners.into_iter().map(|(x, y)| Point2::new(x, y)), true);
+		}
+		add_polyline(&mut self.guide.iter().copied(), false);
+
+		let style = Stroke {
+			width: 1.0,
+			join: Join::Miter,
+			miter_limit: 4.0,
+			start_cap: Cap::Butt,
+			end_cap: Cap::Butt,
+			dash_pattern: Default::default(),
+			dash_offset: 0.0,
+			scale: false,
+		};
+		let mut builder = FragmentBuilder::new();
+		builder.stroke(&style, Affine::IDENTITY, Color::rgb8(0x30, 0x80, 0xff), None, &path);
+		builder.finish()
+	}
+
+	/// Point the view at all strokes on the canvas, upright and with `margin` widget pixels around them. Does nothing
+	/// if the canvas is empty.
+	pub fn fit_to_content(&mut self, margin: f64) {
+		let bounds = match self.canvas.bounds() {
+			Some(bounds) => bounds,
+			None => return,
+		};
+		let available_width = (self.width as f64 - 2.0 * margin).max(1.0);
+		let available_height = (self.height as f64 - 2.0 * margin).max(1.0);
+		let pixel_scale = (available_width / bounds.width().max(1.0)).min(available_height / bounds.height().max(1.0));
+		self.zoom = pixel_scale / self.scale_factor;
+		self.rotation = 0.0;
+		// Put the center of the strokes at the center of the widget
+		let center = bounds.center();
+		self.pan = Vec2::new(center.x, center.y) - self.center().coords / pixel_scale;
+	}
+
+	/// Build the scene showing the canvas through this view
+	pub fn scene(&self) -> Scene {
 		let mut scene = Scene::new();
+		scene.append(&self.background(), None);
+		// The stroke being drawn is only finished into a fragment here, once per render rather than once per sample
+		let active = self.canvas.active_stroke.as_ref().map(ActiveStroke::get_fragment);
 		for layer in &self.canvas.layers {
-			scene.append(layer, Some((self.inv_transform()).ltov()));
+			if let Some(fragment) = layer.fragment(self.pixel_scale()).or(active.as_ref()) {
+				scene.append(fragment, Some(self.layer_transform(layer.origin).ltov()));
+			}
 		}
+		scene.append(&self.outlines(), None);
+		scene
+	}
+}
+
+/// Renders the canvas with vello, from the fragments encoded for each layer
+#[cfg(feature = "backend-vello")]
+pub struct VelloBackend {
+	renderer: Renderer,
+	output: OutputTexture,
+}
+
+#[cfg(feature = "backend-vello")]
+impl VelloBackend {
+	pub fn new(graphics: &Graphics, width: u32, height: u32) -> anyhow::Result<Self> {
+		let renderer = Renderer::new(&graphics.device).map_err(|e| anyhow::format_err!("{e}"))?;
+		Ok(Self {
+			renderer,
+			output: OutputTexture::new(graphics, width, height),
+		})
+	}
+}
+
+/// Vello draws the fragments each layer keeps, so it has nothing of its own to update
+#[cfg(feature = "backend-vello")]
+impl StrokeHooks for VelloBackend {
+	fn reset_strokes(&mut self, _layers: &[Layer]) {}
+
+	fn insert_stroke(&mut self, _index: usize, _layer: &Layer) {}
+
+	fn remove_stroke(&mut self, _index: usize) {}
+
+	fn replace_stroke(&mut self, _index: usize, _layer: &Layer) {}
+}
+
+#[cfg(feature = "backend-vello")]
+impl CanvasBackend for VelloBackend {
+	fn kind(&self) -> BackendKind {
+		BackendKind::Vello
+	}
+
+	fn resize(&mut self, graphics: &Graphics, width: u32, height: u32) {
+		self.output = OutputTexture::new(graphics, width, height);
+	}
 
+	fn render(&mut self, graphics: &Graphics, widget: &CanvasWidget) -> anyhow::Result<()> {
+		let scene = widget.scene();
 		timeit!(
 			"render canvas",
-			self.renderer
-				.render_to_texture(
-					&graphics.device,
-					&graphics.queue,
-					&scene,
-					&self.target_view,
-					self.width,
-					self.height,
-				)
-				.unwrap()
-		);
+			self.renderer.render_to_texture(
+				&graphics.device,
+				&graphics.queue,
+				&scene,
+				self.output.get_texture_view(),
+				self.output.get_width(),
+				self.output.get_height(),
+			)
+		)
+		.map_err(|e| anyhow::format_err!("{e}"))
+	}
+
+	fn output(&self) -> &OutputTexture {
+		&self.output
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_close(a: Point2, b: Point2) {
+		assert!((a - b).norm() < 1e-6, "{a:?} != {b:?}");
+	}
+
+	#[test]
+	fn rotated_view_transforms_round_trip() {
+		let mut widget = CanvasWidget::new(320, 240);
+		widget.pan = Vec2::new(5000.0, -1234.5);
+		widget.zoom = 2.5;
+		widget.rotation = 0.7;
+		widget.set_scale_factor(1.5);
+
+		let to_page = widget.transform();
+		let to_widget = widget.inv_transform();
+		for (x, y) in [(0.0, 0.0), (320.0, 240.0), (17.0, 201.5), (-40.0, 90.0)] {
+			let point = Point2::new(x, y);
+			assert_close(to_widget * (to_page * point), point);
+
+			// Strokes are drawn through their chunk, which has to put them where the view transform does
+			let page = to_page * point;
+			let origin = chunk_origin(page);
+			assert_close(widget.layer_transform(origin) * (page - origin), point);
+		}
+
+		let anchor = Point2::new(100.0, 50.0);
+		let before = widget.transform() * anchor;
+		widget.rotate_around(anchor, -2.0);
+		assert_close(widget.transform() * anchor, before);
+	}
+
+	#[test]
+	fn strokes_in_progress_have_bounds() {
+		let mut canvas = Canvas::new();
+		canvas.start_stroke();
+		assert_eq!(canvas.bounds(), None);
+
+		canvas.move_stroke(Point2::new(5000.0, 10.0), 1.0);
+		canvas.move_stroke(Point2::new(5100.0, 60.0), 1.0);
+		let bounds = canvas.bounds().unwrap();
+		assert!(bounds.contains(Point::new(5000.0, 10.0)));
+		assert!(bounds.contains(Point::new(5100.0, 60.0)));
+		assert!(canvas.layers()[0].hit(Point2::new(5050.0, 35.0), 1.0));
+		assert_eq!(canvas.layers()[0].events().len(), 2);
+
+		canvas.end_stroke();
+		assert_eq!(canvas.bounds(), Some(bounds));
 	}
 }
@@ -0,0 +1,62 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::{
+	canvas::{BackendKind, Background},
+	gpu::GpuConfig,
+	keymap::KeyBinding,
+	palm::PalmRejectionConfig,
+	tablet::TabletConfig,
+};
+
+/// User settings, read from `config.toml` in the skyboard config directory
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+	/// Key chords such as `"Ctrl+Shift+Z"` mapped to the action they trigger, on top of the default keymap. Binding a
+	/// chord to `"unbound"` removes its default binding.
+	pub keymap: HashMap<String, KeyBinding>,
+	/// RGB color of new strokes. Pen bindings to a color replace it while they are in use.
+	pub brush_color: [u8; 3],
+	pub tablet: TabletConfig,
+	pub palm_rejection: PalmRejectionConfig,
+	/// Renderer for the canvas, if not the first one compiled in. Overridden by `--backend`.
+	pub backend: Option<BackendKind>,
+	pub gpu: GpuConfig,
+	pub background: Background,
+	/// Snap shapes and moved selections to the intersections of the background pattern. Toggled with `toggle_grid_snap`.
+	pub snap_to_grid: bool,
+}
+
+impl Config {
+	/// Get the path of the config file, which can be overridden with `SKYBOARD_CONFIG`
+	pub fn path() -> Option<PathBuf> {
+		if let Some(path) = std::env::var_os("SKYBOARD_CONFIG") {
+			return Some(PathBuf::from(path));
+		}
+
+		let mut path = match std::env::var_os("XDG_CONFIG_HOME") {
+			Some(dir) => PathBuf::from(dir),
+			None => {
+				let mut home = PathBuf::from(std::env::var_os("HOME")?);
+				home.push(".config");
+				home
+			}
+		};
+		path.push("skyboard/config.toml");
+		Some(path)
+	}
+
+	/// Load the config file, falling back to the defaults if there is none
+	pub fn load() -> anyhow::Result<Self> {
+		let path = match Self::path() {
+			Some(path) if path.exists() => path,
+			_ => return Ok(Self::default()),
+		};
+
+		log::info!("Loading config from '{}'", path.display());
+		let data = std::fs::read_to_string(&path)?;
+		toml::from_str(&data).map_err(|e| anyhow::format_err!("Invalid config file '{}': {e}", path.display()))
+	}
+}
@@ -0,0 +1,173 @@
+use vello::peniko::Color;
+use wgpu::{RenderPipeline, TextureFormat, TextureView};
+
+use crate::{blit::is_linear_target, Graphics};
+
+/// An outline drawn at the pointer to preview what the current tool will touch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrushCursor {
+	/// Center in widget pixels
+	pub center: [f64; 2],
+	/// Radius in widget pixels
+	pub radius: f64,
+	pub color: Color,
+}
+
+/// Matches the `Cursor` struct in `cursor.wgsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct CursorUniforms {
+	color: [f32; 4],
+	center: [f32; 2],
+	radius: f32,
+	_padding: f32,
+}
+
+/// Draws the brush cursor over whatever was already rendered to a target, so the canvas doesn't have to be
+/// re-rendered when only the pointer moves
+pub struct CursorPipeline {
+	pipeline: RenderPipeline,
+	uniforms: wgpu::Buffer,
+	bind_group: wgpu::BindGroup,
+	/// Whether the target takes linear colors, see `blit::is_linear_target`
+	linear_target: bool,
+}
+
+impl CursorPipeline {
+	pub fn new(graphics: &Graphics, target_format: TextureFormat) -> Self {
+		let shader = crate::shader::load(&graphics.device, "cursor.wgsl");
+		let bind_group_layout = graphics.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Cursor Bind Group Layout"),
+			entries: &[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: None,
+				},
+				count: None,
+			}],
+		});
+
+		let uniforms = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Cursor Uniform Buffer"),
+			size: std::mem::size_of::<CursorUniforms>() as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+		let bind_group = graphics.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Cursor Bind Group"),
+			layout: &bind_group_layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: uniforms.as_entire_binding(),
+			}],
+		});
+
+		let pipeline_layout = graphics.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Cursor Pipeline Layout"),
+			bind_group_layouts: &[&bind_group_layout],
+			push_constant_ranges: &[],
+		});
+		let pipeline = graphics.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Cursor Render Pipeline"),
+			layout: Some(&pipeline_layout),
+			vertex: wgpu::VertexState {
+				module: &shader,
+				entry_point: "vs_main",
+				buffers: &[],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: &shader,
+				entry_point: "fs_main",
+				targets: &[Some(wgpu::ColorTargetState {
+					format: target_format,
+					blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::TriangleList,
+				strip_index_format: None,
+				front_face: wgpu::FrontFace::Ccw,
+				cull_mode: None,
+				polygon_mode: wgpu::PolygonMode::Fill,
+				unclipped_depth: false,
+				conservative: false,
+			},
+			depth_stencil: None,
+			multisample: wgpu::MultisampleState {
+				count: 1,
+				mask: !0,
+				alpha_to_coverage_enabled: false,
+			},
+			multiview: None,
+		});
+
+		Self {
+			pipeline,
+			uniforms,
+			bind_group,
+			linear_target: is_linear_target(target_format),
+		}
+	}
+
+	pub fn draw(&self, graphics: &Graphics, cursor: &BrushCursor, target_view: &TextureView) {
+		let channel = |value: u8| {
+			if self.linear_target {
+				srgb_to_linear(value)
+			} else {
+				value as f32 / 255.0
+			}
+		};
+		let uniforms = CursorUniforms {
+			color: [
+				channel(cursor.color.r),
+				channel(cursor.color.g),
+				channel(cursor.color.b),
+				cursor.color.a as f32 / 255.0,
+			],
+			center: [cursor.center[0] as f32, cursor.center[1] as f32],
+			radius: cursor.radius as f32,
+			_padding: 0.0,
+		};
+		graphics.queue.write_buffer(&self.uniforms, 0, unsafe {
+			std::slice::from_raw_parts(
+				&uniforms as *const CursorUniforms as *const u8,
+				std::mem::size_of::<CursorUniforms>(),
+			)
+		});
+
+		let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+			label: Some("Cursor Command Encoder"),
+		});
+		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("Cursor Render Pass"),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view: target_view,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: wgpu::LoadOp::Load,
+					store: true,
+				},
+			})],
+			depth_stencil_attachment: None,
+		});
+		render_pass.set_pipeline(&self.pipeline);
+		render_pass.set_bind_group(0, &self.bind_group, &[]);
+		render_pass.draw(0..3, 0..1);
+		drop(render_pass);
+		let commands = encoder.finish();
+		graphics.queue.submit([commands]);
+	}
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+	let value = value as f32 / 255.0;
+	if value <= 0.04045 {
+		value / 12.92
+	} else {
+		((value + 0.055) / 1.055).powf(2.4)
+	}
+}
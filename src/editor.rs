@@ -0,0 +1,727 @@
+use std::time::Instant;
+
+use linalg::prelude::*;
+use vello::peniko::Color;
+use winit::event::{ModifiersState, MouseButton, MouseScrollDelta, TouchPhase, VirtualKeyCode};
+
+use crate::{
+	canvas::CanvasWidget,
+	config::Config,
+	cursor::BrushCursor,
+	gesture::{Gesture, GestureRecognizer, GestureSettings},
+	keymap::{Action, Keymap},
+	palm::{PalmRejector, TouchFilter},
+	record::InputEvent,
+	tablet::TabletDevices,
+	tool::{PointerEvent, PointerSource, ToolKind, ToolManager},
+};
+
+/// Zoom levels are powers of two of the canvas zoom
+const MIN_ZOOM_LEVEL: f64 = -8.0;
+const MAX_ZOOM_LEVEL: f64 = 8.0;
+/// Logical pixels of trackpad scrolling needed to change the zoom level by one
+const PIXELS_PER_ZOOM_LEVEL: f64 = 200.0;
+/// View rotations closer than this to upright snap to it
+const ROTATION_SNAP: f64 = 5.0 * std::f64::consts::PI / 180.0;
+/// Rotation applied by a single rotate shortcut
+const ROTATION_STEP: f64 = 15.0 * std::f64::consts::PI / 180.0;
+
+/// What a frontend has to redraw after the editor handled input, from least to most work
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Redraw {
+	#[default]
+	None,
+	/// Only the overlays such as the brush cursor changed
+	Overlay,
+	/// The canvas has to be re-rendered
+	Canvas,
+}
+
+/// The windowless part of the app: turns input into edits of the document and its view. Frontends feed it input
+/// and draw `canvas` and `cursor()` when `take_redraw` says so.
+pub struct Editor {
+	pub canvas: CanvasWidget,
+	old_mouse_pos: Point2,
+	/// Where the mouse or pen is hovering or pressing, if it is over the widget
+	pointer_pos: Option<Point2>,
+	/// The mouse button whose press is being handled by a tool
+	mouse_button: Option<MouseButton>,
+	pen_pos: Point2,
+	current_pressure: f32,
+	zoom_level: f64,
+	/// Rotation of the view in radians before snapping
+	rotation: f64,
+	gestures: GestureRecognizer,
+	palm_rejector: PalmRejector,
+	keymap: Keymap,
+	modifiers: ModifiersState,
+	/// The key holding a temporary tool override
+	held_key: Option<VirtualKeyCode>,
+	tools: ToolManager,
+	tablets: TabletDevices,
+	/// Color of new strokes, unless a pen binding overrides it
+	brush_color: Color,
+	redraw: Redraw,
+}
+
+impl Editor {
+	pub fn new(config: Config, width: u32, height: u32) -> anyhow::Result<Self> {
+		let [r, g, b] = config.brush_color;
+		let brush_color = Color::rgb8(r, g, b);
+		let mut canvas = CanvasWidget::new(width, height);
+		canvas.canvas.color = brush_color;
+		canvas.background = config.background;
+		canvas.snap_to_grid = config.snap_to_grid;
+		Ok(Self {
+			canvas,
+			old_mouse_pos: Point2::new(0.0, 0.0),
+			pointer_pos: None,
+			mouse_button: None,
+			pen_pos: Point2::new(0.0, 0.0),
+			current_pressure: 0.0,
+			zoom_level: 0.0,
+			rotation: 0.0,
+			gestures: GestureRecognizer::new(GestureSettings::default()),
+			palm_rejector: PalmRejector::new(config.palm_rejection),
+			keymap: Keymap::new(&config.keymap)?,
+			modifiers: ModifiersState::empty(),
+			held_key: None,
+			tools: ToolManager::new(),
+			tablets: TabletDevices::new(config.tablet)?,
+			brush_color,
+			redraw: Redraw::Canvas,
+		})
+	}
+
+	/// Get what has to be redrawn since the last call
+	pub fn take_redraw(&mut self) -> Redraw {
+		std::mem::take(&mut self.redraw)
+	}
+
+	fn invalidate_canvas(&mut self) {
+		self.redraw = Redraw::Canvas;
+	}
+
+	fn invalidate_overlay(&mut self) {
+		self.redraw = self.redraw.max(Redraw::Overlay);
+	}
+
+	pub fn resize(&mut self, width: u32, height: u32) {
+		self.canvas.resize(width, height);
+		self.invalidate_canvas();
+	}
+
+	/// Move to a display with a different scale factor. Input positions are in physical pixels of that display.
+	pub fn set_scale_factor(&mut self, scale_factor: f64) {
+		self.canvas.set_scale_factor(scale_factor);
+		self.invalidate_canvas();
+	}
+
+	/// Get the outline to draw at the pointer, if the tool in use has one
+	pub fn cursor(&self) -> Option<BrushCursor> {
+		self.pointer_pos.and_then(|pos| self.tools.cursor(&self.canvas, pos))
+	}
+
+	/// Handle input that happened at `time`
+	pub fn handle_input(&mut self, input: InputEvent, time: Instant) {
+		match input {
+			InputEvent::MouseButton { button, pressed } => self.handle_mouse_button(button, pressed),
+			InputEvent::MouseMoved { x, y } => self.handle_mouse_moved(Point2::new(x, y)),
+			InputEvent::MouseLeft => self.set_pointer_pos(None),
+			InputEvent::Wheel { delta } => self.handle_mouse_wheel(delta),
+			InputEvent::Key { key, pressed } => self.handle_key(key, pressed),
+			InputEvent::Modifiers { modifiers } => self.modifiers = modifiers,
+			InputEvent::Resized { width, height } => self.resize(width, height),
+			InputEvent::ScaleFactorChanged { scale_factor } => self.set_scale_factor(scale_factor),
+			InputEvent::Touch { id, phase, x, y, force } => self.handle_touch(id, phase, Point2::new(x, y), force, time),
+			InputEvent::PenDown
+			| InputEvent::PenUp
+			| InputEvent::PenMoved { .. }
+			| InputEvent::PenPressure { .. }
+			| InputEvent::PenProximity { .. }
+			| InputEvent::PenTool { .. }
+			| InputEvent::PenButton { .. } => self.handle_pen(input, time),
+		}
+	}
+
+	fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
+		let event = PointerEvent {
+			pos: self.old_mouse_pos,
+			pressure: 1.0,
+			modifiers: self.modifiers,
+		};
+
+		if pressed {
+			if self.mouse_button.is_some() {
+				return;
+			}
+
+			let started = match button {
+				MouseButton::Left => self.tools.pointer_down(PointerSource::Mouse, &mut self.canvas, event),
+				MouseButton::Middle => self
+					.tools
+					.pointer_down_with(ToolKind::Pan, PointerSource::Mouse, &mut self.canvas, event),
+				_ => false,
+			};
+			if started {
+				self.mouse_button = Some(button);
+				self.invalidate_canvas();
+			}
+		} else if self.mouse_button == Some(button) {
+			self.mouse_button = None;
+			if self.tools.pointer_up(PointerSource::Mouse, &mut self.canvas, event) {
+				self.invalidate_canvas();
+			}
+		}
+	}
+
+	fn handle_mouse_moved(&mut self, position: Point2) {
+		let event = PointerEvent {
+			pos: position,
+			pressure: 1.0,
+			modifiers: self.modifiers,
+		};
+		self.tools.pointer_move(PointerSource::Mouse, &mut self.canvas, event);
+		if self.tools.is_pressed(PointerSource::Mouse) {
+			self.invalidate_canvas();
+		}
+
+		self.old_mouse_pos = position;
+		self.set_pointer_pos(Some(position));
+	}
+
+	/// Move the brush cursor. Only the overlay is redrawn, not the canvas.
+	fn set_pointer_pos(&mut self, pos: Option<Point2>) {
+		self.pointer_pos = pos;
+		self.invalidate_overlay();
+	}
+
+	fn handle_key(&mut self, key: VirtualKeyCode, pressed: bool) {
+		if pressed {
+			if let Some(action) = self.keymap.resolve(self.modifiers, key) {
+				if action == Action::HoldPan {
+					self.held_key = Some(key);
+				}
+				self.handle_action(action);
+			}
+		} else if self.held_key == Some(key) {
+			self.held_key = None;
+			self.update_overrides();
+		}
+	}
+
+	pub fn handle_action(&mut self, action: Action) {
+		log::debug!("Action {action:?}");
+		let center = self.canvas.center();
+
+		match action {
+			Action::Undo => {
+				if self.canvas.canvas.undo() {
+					self.invalidate_canvas();
+				}
+			}
+			Action::Redo => {
+				if self.canvas.canvas.redo() {
+					self.invalidate_canvas();
+				}
+			}
+			Action::PenTool => self.tools.set_active(ToolKind::Pen),
+			Action::EraserTool => self.tools.set_active(ToolKind::Eraser),
+			Action::LassoTool => self.tools.set_active(ToolKind::Lasso),
+			Action::PanTool => self.tools.set_active(ToolKind::Pan),
+			Action::LineTool => self.tools.set_active(ToolKind::Line),
+			Action::RectangleTool => self.tools.set_active(ToolKind::Rectangle),
+			Action::EllipseTool => self.tools.set_active(ToolKind::Ellipse),
+			Action::HoldPan => self.update_overrides(),
+			Action::ZoomIn => self.zoom_by(1.0, center),
+			Action::ZoomOut => self.zoom_by(-1.0, center),
+			Action::RotateLeft => self.rotate_by(-ROTATION_STEP, center),
+			Action::RotateRight => self.rotate_by(ROTATION_STEP, center),
+			Action::ResetRotation => self.reset_rotation(),
+			Action::ResetView => {
+				self.zoom_level = 0.0;
+				self.rotation = 0.0;
+				self.canvas.pan = Vec2::zero();
+				self.canvas.zoom = 1.0;
+				self.canvas.rotation = 0.0;
+				self.invalidate_canvas();
+			}
+			Action::CycleBackground => {
+				let background = &mut self.canvas.background;
+				background.pattern = background.pattern.next();
+				log::info!("Background pattern {:?}", background.pattern);
+				self.invalidate_canvas();
+			}
+			Action::ToggleGridSnap => {
+				self.canvas.snap_to_grid = !self.canvas.snap_to_grid;
+				log::info!("Snapping to the grid {}", if self.canvas.snap_to_grid { "on" } else { "off" });
+			}
+			Action::Save => log::warn!("Saving documents is not implemented yet"),
+		}
+
+		// The brush cursor may have changed with the tool
+		self.invalidate_overlay();
+	}
+
+	fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta) {
+		let levels = match delta {
+			MouseScrollDelta::LineDelta(_h, v) => v as f64,
+			MouseScrollDelta::PixelDelta(pos) => pos.y / (PIXELS_PER_ZOOM_LEVEL * self.canvas.scale_factor()),
+		};
+		self.zoom_by(levels, self.old_mouse_pos);
+	}
+
+	/// Change the zoom level, keeping the page point under `anchor` in place
+	fn zoom_by(&mut self, levels: f64, anchor: Point2) {
+		let zoom_level = (self.zoom_level + levels).clamp(MIN_ZOOM_LEVEL, MAX_ZOOM_LEVEL);
+		if zoom_level != self.zoom_level {
+			self.zoom_level = zoom_level;
+			self.canvas.zoom_around(anchor, 2.0f64.powf(self.zoom_level));
+
+			self.invalidate_canvas();
+		}
+	}
+
+	/// Rotate the view, keeping the page point under `anchor` in place. The result snaps to upright when close.
+	fn rotate_by(&mut self, angle: f64, anchor: Point2) {
+		self.rotation = (self.rotation + angle).rem_euclid(std::f64::consts::TAU);
+		let upright_distance = self.rotation.min(std::f64::consts::TAU - self.rotation);
+		let rotation = if upright_distance < ROTATION_SNAP {
+			0.0
+		} else {
+			self.rotation
+		};
+		self.canvas.rotate_around(anchor, rotation);
+
+		self.invalidate_canvas();
+	}
+
+	/// Put the view back upright around the center of the widget
+	fn reset_rotation(&mut self) {
+		self.rotation = 0.0;
+		let center = self.canvas.center();
+		self.canvas.rotate_around(center, 0.0);
+
+		self.invalidate_canvas();
+	}
+
+	fn handle_touch(&mut self, id: u64, mut phase: TouchPhase, position: Point2, force: Option<f64>, time: Instant) {
+		match self.palm_rejector.filter(id, phase, force, time) {
+			TouchFilter::Accept => {}
+			TouchFilter::Reject => return,
+			TouchFilter::Cancel => phase = TouchPhase::Cancelled,
+		}
+
+		let pressure = force.map_or(1.0, |force| force as f32);
+		let gesture = match self.gestures.handle_touch(id, phase, position) {
+			Some(gesture) => gesture,
+			None => return,
+		};
+
+		match gesture {
+			Gesture::DrawStart(pos) => {
+				let event = PointerEvent {
+					pos,
+					pressure,
+					modifiers: self.modifiers,
+				};
+				self.tools.pointer_down(PointerSource::Touch, &mut self.canvas, event);
+			}
+			Gesture::DrawMove(pos) => {
+				let event = PointerEvent {
+					pos,
+					pressure,
+					modifiers: self.modifiers,
+				};
+				self.tools.pointer_move(PointerSource::Touch, &mut self.canvas, event);
+			}
+			Gesture::DrawEnd => {
+				let event = PointerEvent {
+					pos: position,
+					pressure,
+					modifiers: self.modifiers,
+				};
+				self.tools.pointer_up(PointerSource::Touch, &mut self.canvas, event);
+			}
+			Gesture::DrawCancel => {
+				if self.tools.is_pressed(PointerSource::Touch) {
+					self.tools.cancel(&mut self.canvas);
+				}
+			}
+			Gesture::Pan(delta) => {
+				self.canvas.pan -= self.canvas.transform() * delta;
+			}
+			Gesture::Pinch {
+				centroid,
+				translation,
+				scale,
+				rotation,
+			} => {
+				self.canvas.pan -= self.canvas.transform() * translation;
+				self.zoom_by(scale.log2(), centroid);
+				// The page follows the fingers, so it turns opposite to the widget-to-page rotation
+				self.rotate_by(-rotation, centroid);
+			}
+		}
+
+		self.invalidate_canvas();
+	}
+
+	fn handle_pen(&mut self, input: InputEvent, time: Instant) {
+		let event = PointerEvent {
+			pos: self.pen_pos,
+			pressure: self.current_pressure,
+			modifiers: self.modifiers,
+		};
+
+		match input {
+			InputEvent::PenDown => {
+				self.palm_rejector.handle_pen_pressed(true, time);
+				if self.tools.pointer_down(PointerSource::Pen, &mut self.canvas, event) {
+					self.invalidate_canvas();
+				}
+			}
+			InputEvent::PenUp => {
+				self.palm_rejector.handle_pen_pressed(false, time);
+				if self.tools.pointer_up(PointerSource::Pen, &mut self.canvas, event) {
+					self.invalidate_canvas();
+				}
+			}
+			InputEvent::PenMoved { x, y } => {
+				self.pen_pos = Point2::new(x, y);
+				let event = PointerEvent {
+					pos: self.pen_pos,
+					..event
+				};
+				self.tools.pointer_move(PointerSource::Pen, &mut self.canvas, event);
+				if self.tools.is_pressed(PointerSource::Pen) {
+					self.invalidate_canvas();
+				}
+				self.set_pointer_pos(Some(self.pen_pos));
+			}
+			InputEvent::PenPressure { pressure } => self.current_pressure = pressure,
+			InputEvent::PenProximity { device, near } => self.palm_rejector.handle_pen_proximity(device, near, time),
+			InputEvent::PenTool { device, eraser } => {
+				self.tablets.handle_tool(device, eraser);
+				self.update_overrides();
+			}
+			InputEvent::PenButton { device, button, pressed } => {
+				self.tablets.handle_button(device, button, pressed);
+				self.update_overrides();
+			}
+			_ => {}
+		}
+	}
+
+	/// Apply the temporary tool and color from held keys and pen bindings
+	fn update_overrides(&mut self) {
+		let binding = self.tablets.binding();
+		let temporary = match self.held_key {
+			Some(_) => Some(ToolKind::Pan),
+			None => binding.and_then(|binding| binding.tool()),
+		};
+		self.tools.set_temporary(temporary);
+		self.canvas.canvas.color = binding.and_then(|binding| binding.color()).unwrap_or(self.brush_color);
+		self.invalidate_overlay();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+	use crate::{
+		canvas::{Background, Pattern},
+		tablet::{TabletBinding, TabletConfig},
+		tool::ANGLE_STEP,
+	};
+
+	fn editor() -> Editor {
+		Editor::new(Config::default(), 800, 600).unwrap()
+	}
+
+	/// An editor on a 32 unit grid with snapping on
+	fn snapping_editor() -> Editor {
+		let config = Config {
+			background: Background {
+				pattern: Pattern::Grid,
+				..Default::default()
+			},
+			snap_to_grid: true,
+			..Default::default()
+		};
+		Editor::new(config, 800, 600).unwrap()
+	}
+
+	/// Get the page positions of the samples of a stroke
+	fn stroke_points(editor: &Editor, index: usize) -> Vec<Point2> {
+		let layer = &editor.canvas.canvas.layers()[index];
+		layer.events().iter().map(|event| event.pos + layer.origin()).collect()
+	}
+
+	fn feed(editor: &mut Editor, start: Instant, events: &[(u64, InputEvent)]) {
+		for &(millis, event) in events {
+			editor.handle_input(event, start + Duration::from_millis(millis));
+		}
+	}
+
+	fn mouse_drag(from: (f64, f64), to: (f64, f64)) -> [(u64, InputEvent); 4] {
+		let button = MouseButton::Left;
+		[
+			(0, InputEvent::MouseMoved { x: from.0, y: from.1 }),
+			(0, InputEvent::MouseButton { button, pressed: true }),
+			(10, InputEvent::MouseMoved { x: to.0, y: to.1 }),
+			(20, InputEvent::MouseButton { button, pressed: false }),
+		]
+	}
+
+	fn touch(id: u64, phase: TouchPhase, x: f64, y: f64) -> InputEvent {
+		InputEvent::Touch {
+			id,
+			phase,
+			x,
+			y,
+			force: None,
+		}
+	}
+
+	#[test]
+	fn mouse_drag_draws_stroke_and_undo_removes_it() {
+		let mut editor = editor();
+		let start = Instant::now();
+		feed(&mut editor, start, &mouse_drag((10.0, 10.0), (50.0, 20.0)));
+		assert_eq!(editor.canvas.canvas.stroke_count(), 1);
+		assert_eq!(editor.take_redraw(), Redraw::Canvas);
+
+		feed(
+			&mut editor,
+			start,
+			&[
+				(
+					30,
+					InputEvent::Modifiers {
+						modifiers: ModifiersState::CTRL,
+					},
+				),
+				(
+					40,
+					InputEvent::Key {
+						key: VirtualKeyCode::Z,
+						pressed: true,
+					},
+				),
+			],
+		);
+		assert_eq!(editor.canvas.canvas.stroke_count(), 0);
+	}
+
+	#[test]
+	fn held_space_pans_instead_of_drawing() {
+		let mut editor = editor();
+		let start = Instant::now();
+		let space = VirtualKeyCode::Space;
+		feed(
+			&mut editor,
+			start,
+			&[(
+				0,
+				InputEvent::Key {
+					key: space,
+					pressed: true,
+				},
+			)],
+		);
+		feed(&mut editor, start, &mouse_drag((100.0, 100.0), (130.0, 140.0)));
+		feed(
+			&mut editor,
+			start,
+			&[(
+				30,
+				InputEvent::Key {
+					key: space,
+					pressed: false,
+				},
+			)],
+		);
+
+		assert_eq!(editor.canvas.canvas.stroke_count(), 0);
+		assert_eq!(editor.canvas.pan, Vec2::new(-30.0, -40.0));
+	}
+
+	#[test]
+	fn pen_color_bindings_apply_while_held() {
+		let config = Config {
+			brush_color: [0, 0, 255],
+			tablet: TabletConfig {
+				buttons: [("2".to_owned(), TabletBinding::Color([255, 0, 0]))].into_iter().collect(),
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let mut editor = Editor::new(config, 800, 600).unwrap();
+		let button = |pressed| InputEvent::PenButton {
+			device: 0,
+			button: 2,
+			pressed,
+		};
+		let stroke = [
+			(0, InputEvent::PenMoved { x: 10.0, y: 10.0 }),
+			(0, InputEvent::PenPressure { pressure: 0.5 }),
+			(0, InputEvent::PenDown),
+			(10, InputEvent::PenMoved { x: 50.0, y: 20.0 }),
+			(20, InputEvent::PenUp),
+		];
+		let start = Instant::now();
+		feed(&mut editor, start, &[(0, button(true))]);
+		feed(&mut editor, start, &stroke);
+		feed(&mut editor, start, &[(30, button(false))]);
+		feed(&mut editor, start, &stroke);
+
+		let colors: Vec<_> = editor.canvas.canvas.layers().iter().map(|layer| layer.color()).collect();
+		assert_eq!(colors, [Color::rgb8(255, 0, 0), Color::rgb8(0, 0, 255)]);
+	}
+
+	#[test]
+	fn strokes_keep_their_page_size_on_hidpi_displays() {
+		let mut low = editor();
+		let mut high = editor();
+		high.resize(1600, 1200);
+		high.set_scale_factor(2.0);
+		let start = Instant::now();
+		feed(&mut low, start, &mouse_drag((10.0, 10.0), (50.0, 20.0)));
+		feed(&mut high, start, &mouse_drag((20.0, 20.0), (100.0, 40.0)));
+
+		assert_eq!(low.canvas.canvas.bounds(), high.canvas.canvas.bounds());
+		assert_eq!(high.cursor().unwrap().radius, 2.0 * low.cursor().unwrap().radius);
+	}
+
+	#[test]
+	fn shift_keeps_lines_to_angle_steps() {
+		let mut editor = editor();
+		let start = Instant::now();
+		editor.handle_action(Action::LineTool);
+		feed(
+			&mut editor,
+			start,
+			&[(
+				0,
+				InputEvent::Modifiers {
+					modifiers: ModifiersState::SHIFT,
+				},
+			)],
+		);
+		feed(&mut editor, start, &mouse_drag((100.0, 100.0), (200.0, 120.0)));
+
+		let points = stroke_points(&editor, 0);
+		let offset = points[1] - points[0];
+		assert!((offset.y.atan2(offset.x) - ANGLE_STEP).abs() < 1e-9);
+	}
+
+	#[test]
+	fn shapes_snap_to_the_grid() {
+		let mut editor = snapping_editor();
+		let start = Instant::now();
+		editor.handle_action(Action::RectangleTool);
+		feed(&mut editor, start, &mouse_drag((30.0, 35.0), (95.0, 70.0)));
+
+		let points = stroke_points(&editor, 0);
+		assert!((points[0] - Point2::new(32.0, 32.0)).norm() < 1e-9);
+		assert!((points[2] - Point2::new(96.0, 64.0)).norm() < 1e-9);
+	}
+
+	#[test]
+	fn moved_selections_snap_to_the_grid() {
+		let mut editor = snapping_editor();
+		let start = Instant::now();
+		// Freehand strokes don't snap
+		feed(&mut editor, start, &mouse_drag((110.0, 100.0), (150.0, 110.0)));
+		let button = MouseButton::Left;
+		editor.handle_action(Action::LassoTool);
+		feed(
+			&mut editor,
+			start,
+			&[
+				(30, InputEvent::MouseMoved { x: 50.0, y: 50.0 }),
+				(30, InputEvent::MouseButton { button, pressed: true }),
+				(40, InputEvent::MouseMoved { x: 200.0, y: 50.0 }),
+				(50, InputEvent::MouseMoved { x: 200.0, y: 200.0 }),
+				(60, InputEvent::MouseMoved { x: 50.0, y: 200.0 }),
+				(70, InputEvent::MouseButton { button, pressed: false }),
+			],
+		);
+		let on_grid = |x: f64| (x / 32.0 - (x / 32.0).round()).abs() < 1e-9;
+		let before = stroke_points(&editor, 0);
+		assert!(!on_grid(before[0].x));
+
+		feed(&mut editor, start, &mouse_drag((120.0, 105.0), (133.0, 121.0)));
+		// The stroke itself snaps, not its bounds padded by the stroke width
+		let after = stroke_points(&editor, 0);
+		assert!(on_grid(after[0].x) && on_grid(after[0].y), "{:?} is off the grid", after[0]);
+		let offset = after[0] - before[0];
+		for (a, b) in before.iter().zip(&after) {
+			assert!((*b - *a - offset).norm() < 1e-9);
+		}
+	}
+
+	#[test]
+	fn hovering_only_redraws_overlay() {
+		let mut editor = editor();
+		editor.take_redraw();
+		editor.handle_input(InputEvent::MouseMoved { x: 5.0, y: 5.0 }, Instant::now());
+		assert_eq!(editor.take_redraw(), Redraw::Overlay);
+		assert!(editor.cursor().is_some());
+	}
+
+	#[test]
+	fn pinch_zooms_around_fingers() {
+		let mut editor = editor();
+		let start = Instant::now();
+		feed(
+			&mut editor,
+			start,
+			&[
+				(0, touch(1, TouchPhase::Started, 100.0, 100.0)),
+				(0, touch(2, TouchPhase::Started, 200.0, 100.0)),
+				(10, touch(2, TouchPhase::Moved, 300.0, 100.0)),
+				(20, touch(2, TouchPhase::Ended, 300.0, 100.0)),
+				(20, touch(1, TouchPhase::Ended, 100.0, 100.0)),
+			],
+		);
+
+		assert_eq!(editor.canvas.canvas.stroke_count(), 0);
+		assert!((editor.canvas.zoom - 2.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn touch_near_pen_is_rejected() {
+		let mut editor = editor();
+		let start = Instant::now();
+		feed(
+			&mut editor,
+			start,
+			&[
+				(0, InputEvent::PenProximity { device: 0, near: true }),
+				(0, InputEvent::PenMoved { x: 0.0, y: 0.0 }),
+				// The pen hovers still for longer than the grace period
+				(1000, touch(1, TouchPhase::Started, 10.0, 10.0)),
+				(1010, touch(1, TouchPhase::Moved, 50.0, 50.0)),
+				(1020, touch(1, TouchPhase::Ended, 50.0, 50.0)),
+				(1500, InputEvent::PenProximity { device: 0, near: false }),
+			],
+		);
+		assert_eq!(editor.canvas.canvas.stroke_count(), 0);
+
+		// Long after the pen left, touches draw again
+		feed(
+			&mut editor,
+			start,
+			&[
+				(2000, touch(2, TouchPhase::Started, 10.0, 10.0)),
+				(2010, touch(2, TouchPhase::Moved, 50.0, 50.0)),
+				(2020, touch(2, TouchPhase::Ended, 50.0, 50.0)),
+			],
+		);
+		assert_eq!(editor.canvas.canvas.stroke_count(), 1);
+	}
+}
@@ -0,0 +1,248 @@
+use linalg::prelude::*;
+use winit::event::TouchPhase;
+
+/// What a single finger on the touch screen does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SingleTouchMode {
+	Draw,
+	Pan,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GestureSettings {
+	pub single_touch: SingleTouchMode,
+	/// Whether twisting two fingers rotates the view
+	pub rotate: bool,
+}
+
+impl Default for GestureSettings {
+	fn default() -> Self {
+		Self {
+			single_touch: SingleTouchMode::Draw,
+			rotate: false,
+		}
+	}
+}
+
+/// A gesture recognized from touch input, in widget coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+	DrawStart(Point2),
+	DrawMove(Point2),
+	DrawEnd,
+	/// The stroke being drawn should be discarded, e.g. because a second finger turned it into a pinch
+	DrawCancel,
+	Pan(Vec2),
+	/// Two fingers moved. `translation` is the movement of their centroid, `scale` the ratio of their new to old
+	/// distance and `rotation` the change in their angle in radians, all to be applied around `centroid`.
+	Pinch {
+		centroid: Point2,
+		translation: Vec2,
+		scale: f64,
+		rotation: f64,
+	},
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GestureState {
+	Idle,
+	Drawing,
+	Panning,
+	/// More than one finger has been down since the last time all fingers were lifted
+	MultiTouch,
+}
+
+/// Turns a stream of touch events into gestures, tracking each finger by its id
+pub struct GestureRecognizer {
+	pub settings: GestureSettings,
+	/// Active touches in the order they went down
+	touches: Vec<(u64, Point2)>,
+	state: GestureState,
+}
+
+impl GestureRecognizer {
+	pub fn new(settings: GestureSettings) -> Self {
+		Self {
+			settings,
+			touches: Vec::new(),
+			state: GestureState::Idle,
+		}
+	}
+
+	pub fn handle_touch(&mut self, id: u64, phase: TouchPhase, position: Point2) -> Option<Gesture> {
+		match phase {
+			TouchPhase::Started => self.touch_started(id, position),
+			TouchPhase::Moved => self.touch_moved(id, position),
+			TouchPhase::Ended => self.touch_ended(id, false),
+			TouchPhase::Cancelled => self.touch_ended(id, true),
+		}
+	}
+
+	fn touch_started(&mut self, id: u64, position: Point2) -> Option<Gesture> {
+		self.touches.retain(|&(other, _)| other != id);
+		self.touches.push((id, position));
+
+		match (self.touches.len(), self.state) {
+			(1, GestureState::Idle) => match self.settings.single_touch {
+				SingleTouchMode::Draw => {
+					self.state = GestureState::Drawing;
+					Some(Gesture::DrawStart(position))
+				}
+				SingleTouchMode::Pan => {
+					self.state = GestureState::Panning;
+					None
+				}
+			},
+			(1, _) => None,
+			(_, GestureState::Drawing) => {
+				self.state = GestureState::MultiTouch;
+				Some(Gesture::DrawCancel)
+			}
+			_ => {
+				self.state = GestureState::MultiTouch;
+				None
+			}
+		}
+	}
+
+	fn touch_moved(&mut self, id: u64, position: Point2) -> Option<Gesture> {
+		let index = self.touches.iter().position(|&(other, _)| other == id)?;
+		let old_touches = [self.touches[0].1, self.touches.get(1).map_or(self.touches[0].1, |t| t.1)];
+		let old_position = std::mem::replace(&mut self.touches[index].1, position);
+
+		match self.state {
+			GestureState::Drawing => Some(Gesture::DrawMove(position)),
+			GestureState::Panning => Some(Gesture::Pan(position - old_position)),
+			GestureState::MultiTouch if index < 2 && self.touches.len() >= 2 => {
+				let new_touches = [self.touches[0].1, self.touches[1].1];
+				let old_span = old_touches[1] - old_touches[0];
+				let new_span = new_touches[1] - new_touches[0];
+				let old_centroid = old_touches[0] + old_span * 0.5;
+				let centroid = new_touches[0] + new_span * 0.5;
+
+				let scale = if old_span.norm() > f64::EPSILON {
+					new_span.norm() / old_span.norm()
+				} else {
+					1.0
+				};
+				let rotation = if self.settings.rotate {
+					let angle = new_span.y.atan2(new_span.x) - old_span.y.atan2(old_span.x);
+					// Keep the change in (-pi, pi] so crossing the atan2 branch cut doesn't spin the view
+					(angle + std::f64::consts::PI).rem_euclid(std::f64::consts::TAU) - std::f64::consts::PI
+				} else {
+					0.0
+				};
+
+				Some(Gesture::Pinch {
+					centroid,
+					translation: centroid - old_centroid,
+					scale,
+					rotation,
+				})
+			}
+			_ => None,
+		}
+	}
+
+	fn touch_ended(&mut self, id: u64, cancelled: bool) -> Option<Gesture> {
+		let index = self.touches.iter().position(|&(other, _)| other == id)?;
+		self.touches.remove(index);
+
+		if !self.touches.is_empty() {
+			return None;
+		}
+
+		match std::mem::replace(&mut self.state, GestureState::Idle) {
+			GestureState::Drawing if cancelled => Some(Gesture::DrawCancel),
+			GestureState::Drawing => Some(Gesture::DrawEnd),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn feed(recognizer: &mut GestureRecognizer, events: &[(u64, TouchPhase, f64, f64)]) -> Vec<Gesture> {
+		events
+			.iter()
+			.filter_map(|&(id, phase, x, y)| recognizer.handle_touch(id, phase, Point2::new(x, y)))
+			.collect()
+	}
+
+	#[test]
+	fn single_finger_draws() {
+		let mut recognizer = GestureRecognizer::new(GestureSettings::default());
+		let gestures = feed(
+			&mut recognizer,
+			&[
+				(1, TouchPhase::Started, 0.0, 0.0),
+				(1, TouchPhase::Moved, 5.0, 0.0),
+				(1, TouchPhase::Ended, 5.0, 0.0),
+			],
+		);
+		assert_eq!(
+			gestures,
+			[
+				Gesture::DrawStart(Point2::new(0.0, 0.0)),
+				Gesture::DrawMove(Point2::new(5.0, 0.0)),
+				Gesture::DrawEnd
+			]
+		);
+	}
+
+	#[test]
+	fn single_finger_pans() {
+		let mut recognizer = GestureRecognizer::new(GestureSettings {
+			single_touch: SingleTouchMode::Pan,
+			..Default::default()
+		});
+		let gestures = feed(
+			&mut recognizer,
+			&[
+				(1, TouchPhase::Started, 0.0, 0.0),
+				(1, TouchPhase::Moved, 3.0, 4.0),
+				(1, TouchPhase::Ended, 3.0, 4.0),
+			],
+		);
+		assert_eq!(gestures, [Gesture::Pan(Vec2::new(3.0, 4.0))]);
+	}
+
+	#[test]
+	fn second_finger_cancels_stroke_and_pinches() {
+		let mut recognizer = GestureRecognizer::new(GestureSettings {
+			rotate: true,
+			..Default::default()
+		});
+		let gestures = feed(
+			&mut recognizer,
+			&[
+				(1, TouchPhase::Started, 0.0, 0.0),
+				(2, TouchPhase::Started, 10.0, 0.0),
+				(2, TouchPhase::Moved, 0.0, 20.0),
+				(2, TouchPhase::Ended, 0.0, 20.0),
+				(1, TouchPhase::Moved, 5.0, 5.0),
+				(1, TouchPhase::Ended, 5.0, 5.0),
+			],
+		);
+
+		assert_eq!(gestures.len(), 3);
+		assert_eq!(gestures[0], Gesture::DrawStart(Point2::new(0.0, 0.0)));
+		assert_eq!(gestures[1], Gesture::DrawCancel);
+		match gestures[2] {
+			Gesture::Pinch {
+				centroid,
+				translation,
+				scale,
+				rotation,
+			} => {
+				assert_eq!(centroid, Point2::new(0.0, 10.0));
+				assert_eq!(translation, Vec2::new(-5.0, 10.0));
+				assert!((scale - 2.0).abs() < 1e-9);
+				assert!((rotation - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+			}
+			other => panic!("expected a pinch, got {other:?}"),
+		}
+	}
+}
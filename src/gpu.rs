@@ -0,0 +1,242 @@
+use serde::{
+	de::{DeserializeOwned, IntoDeserializer},
+	Deserialize,
+};
+use wgpu::{Backends, CompositeAlphaMode, TextureFormat};
+
+/// Graphics APIs wgpu can render with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WgpuBackend {
+	/// Let wgpu pick among all the APIs it supports
+	Any,
+	Vulkan,
+	Metal,
+	Dx12,
+	Gl,
+}
+
+impl WgpuBackend {
+	/// Order in which specific APIs are tried when the preferred one has no usable adapter
+	const FALLBACK_ORDER: [WgpuBackend; 4] = [WgpuBackend::Vulkan, WgpuBackend::Metal, WgpuBackend::Dx12, WgpuBackend::Gl];
+
+	pub fn backends(self) -> Backends {
+		match self {
+			WgpuBackend::Any => Backends::all(),
+			WgpuBackend::Vulkan => Backends::VULKAN,
+			WgpuBackend::Metal => Backends::METAL,
+			WgpuBackend::Dx12 => Backends::DX12,
+			WgpuBackend::Gl => Backends::GL,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerPreference {
+	LowPower,
+	HighPerformance,
+}
+
+impl From<PowerPreference> for wgpu::PowerPreference {
+	fn from(preference: PowerPreference) -> Self {
+		match preference {
+			PowerPreference::LowPower => wgpu::PowerPreference::LowPower,
+			PowerPreference::HighPerformance => wgpu::PowerPreference::HighPerformance,
+		}
+	}
+}
+
+/// How frames are handed to the window, see `wgpu::PresentMode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+	AutoVsync,
+	AutoNoVsync,
+	Fifo,
+	FifoRelaxed,
+	Immediate,
+	Mailbox,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+	fn from(mode: PresentMode) -> Self {
+		match mode {
+			PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+			PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+			PresentMode::Fifo => wgpu::PresentMode::Fifo,
+			PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+			PresentMode::Immediate => wgpu::PresentMode::Immediate,
+			PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+		}
+	}
+}
+
+/// Parse a setting as it is spelled in the config, such as `"high_performance"`
+pub fn parse_setting<T: DeserializeOwned>(what: &str, name: &str) -> anyhow::Result<T> {
+	T::deserialize(name.into_deserializer()).map_err(|_: serde::de::value::Error| anyhow::format_err!("Unknown {what} '{name}'"))
+}
+
+/// Which adapter to render with and how to present to the window, read from the `[gpu]` section of the config.
+/// Each setting can be overridden with an environment variable, see `apply_env`, and those with command line flags.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GpuConfig {
+	/// Graphics API to try first
+	pub backend: WgpuBackend,
+	pub power_preference: PowerPreference,
+	/// Only use software adapters such as lavapipe or WARP
+	pub force_fallback_adapter: bool,
+	pub present_mode: PresentMode,
+}
+
+impl Default for GpuConfig {
+	fn default() -> Self {
+		Self {
+			backend: WgpuBackend::Vulkan,
+			power_preference: PowerPreference::HighPerformance,
+			force_fallback_adapter: false,
+			present_mode: PresentMode::AutoVsync,
+		}
+	}
+}
+
+impl GpuConfig {
+	/// Override settings from `SKYBOARD_WGPU_BACKEND`, `SKYBOARD_POWER_PREFERENCE`, `SKYBOARD_FORCE_FALLBACK_ADAPTER`
+	/// and `SKYBOARD_PRESENT_MODE`
+	pub fn apply_env(&mut self) -> anyhow::Result<()> {
+		let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+		if let Some(value) = var("SKYBOARD_WGPU_BACKEND") {
+			self.backend = parse_setting("wgpu backend", &value)?;
+		}
+		if let Some(value) = var("SKYBOARD_POWER_PREFERENCE") {
+			self.power_preference = parse_setting("power preference", &value)?;
+		}
+		if let Some(value) = var("SKYBOARD_FORCE_FALLBACK_ADAPTER") {
+			self.force_fallback_adapter = match value.as_str() {
+				"1" | "true" => true,
+				"0" | "false" => false,
+				_ => {
+					return Err(anyhow::format_err!(
+						"SKYBOARD_FORCE_FALLBACK_ADAPTER should be 1 or 0, not '{value}'"
+					))
+				}
+			};
+		}
+		if let Some(value) = var("SKYBOARD_PRESENT_MODE") {
+			self.present_mode = parse_setting("present mode", &value)?;
+		}
+		Ok(())
+	}
+
+	/// Get the APIs to look for an adapter with, and whether to force a fallback adapter, in the order to try them.
+	/// The configured API comes first, then the others, and software adapters are the last resort.
+	pub fn attempts(&self) -> Vec<(Backends, bool)> {
+		let mut backends = vec![self.backend.backends()];
+		if self.backend != WgpuBackend::Any {
+			backends.extend(
+				WgpuBackend::FALLBACK_ORDER
+					.iter()
+					.filter(|&&backend| backend != self.backend)
+					.map(|backend| backend.backends()),
+			);
+		}
+
+		let mut fallback = vec![self.force_fallback_adapter];
+		if !self.force_fallback_adapter {
+			fallback.push(true);
+		}
+		fallback
+			.into_iter()
+			.flat_map(|force_fallback_adapter| backends.iter().map(move |&backends| (backends, force_fallback_adapter)))
+			.collect()
+	}
+}
+
+/// Pick the configured present mode if the surface supports it, or vsync otherwise
+pub fn choose_present_mode(wanted: PresentMode, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+	let mode = wgpu::PresentMode::from(wanted);
+	let chosen = match wanted {
+		// wgpu resolves these to whatever the surface supports
+		PresentMode::AutoVsync | PresentMode::AutoNoVsync => mode,
+		_ if supported.contains(&mode) => mode,
+		_ => {
+			log::warn!("The surface doesn't support the {mode:?} present mode, only {supported:?}");
+			wgpu::PresentMode::AutoVsync
+		}
+	};
+	log::info!("Presenting with {chosen:?}");
+	chosen
+}
+
+/// Surface formats the canvas can be shown in exactly, best first. The canvas values can be copied to the plain 8-bit
+/// formats as they are, while the sRGB ones need them decoded in the blit and encode them again on write.
+const SURFACE_FORMATS: [TextureFormat; 4] = [
+	TextureFormat::Bgra8Unorm,
+	TextureFormat::Rgba8Unorm,
+	TextureFormat::Bgra8UnormSrgb,
+	TextureFormat::Rgba8UnormSrgb,
+];
+
+/// Pick the best format the surface supports, or the first one it prefers if it supports none of `SURFACE_FORMATS`
+pub fn choose_surface_format(supported: &[TextureFormat]) -> anyhow::Result<TextureFormat> {
+	let chosen = SURFACE_FORMATS
+		.into_iter()
+		.find(|format| supported.contains(format))
+		.or_else(|| supported.first().copied())
+		.ok_or_else(|| anyhow::format_err!("The surface doesn't support any format with this adapter"))?;
+	log::info!("Presenting in {chosen:?}, the surface supports {supported:?}");
+	Ok(chosen)
+}
+
+/// Pick an opaque alpha mode if the surface supports one, since the canvas is never transparent
+pub fn choose_alpha_mode(supported: &[CompositeAlphaMode]) -> CompositeAlphaMode {
+	if supported.contains(&CompositeAlphaMode::Opaque) {
+		CompositeAlphaMode::Opaque
+	} else {
+		supported.first().copied().unwrap_or(CompositeAlphaMode::Auto)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn preferred_api_is_tried_first_and_software_last() {
+		let config = GpuConfig {
+			backend: WgpuBackend::Gl,
+			..Default::default()
+		};
+		let attempts = config.attempts();
+		assert_eq!(attempts.len(), 8);
+		assert_eq!(attempts[0], (Backends::GL, false));
+		assert_eq!(attempts[1], (Backends::VULKAN, false));
+		assert!(attempts[4..]
+			.iter()
+			.all(|&(_, force_fallback_adapter)| force_fallback_adapter));
+	}
+
+	#[test]
+	fn surface_format_prefers_exact_formats() {
+		let supported = [
+			TextureFormat::Rgba16Float,
+			TextureFormat::Bgra8UnormSrgb,
+			TextureFormat::Bgra8Unorm,
+		];
+		assert_eq!(choose_surface_format(&supported).unwrap(), TextureFormat::Bgra8Unorm);
+		assert_eq!(choose_surface_format(&supported[..2]).unwrap(), TextureFormat::Bgra8UnormSrgb);
+		assert_eq!(choose_surface_format(&supported[..1]).unwrap(), TextureFormat::Rgba16Float);
+		assert!(choose_surface_format(&[]).is_err());
+	}
+
+	#[test]
+	fn settings_parse_like_the_config() {
+		assert_eq!(parse_setting::<WgpuBackend>("wgpu backend", "gl").unwrap(), WgpuBackend::Gl);
+		assert_eq!(
+			parse_setting::<PresentMode>("present mode", "auto_no_vsync").unwrap(),
+			PresentMode::AutoNoVsync
+		);
+		assert!(parse_setting::<PowerPreference>("power preference", "fast").is_err());
+	}
+}
@@ -0,0 +1,157 @@
+use std::{
+	fs::File,
+	io::{BufReader, BufWriter},
+	path::Path,
+	sync::mpsc,
+};
+
+use crate::{
+	canvas::{self, BackendKind, CanvasBackend, CanvasWidget, StrokeSync},
+	Graphics,
+};
+
+/// An 8-bit RGBA image with tightly packed rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+	pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+		let i = (y as usize * self.width as usize + x as usize) * 4;
+		[self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+	}
+
+	pub fn load_png(path: &Path) -> anyhow::Result<Self> {
+		let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+		// Expand palettes and low bit depths so every image decodes to 8-bit channels
+		decoder.set_transformations(png::Transformations::normalize_to_color8());
+		let mut reader = decoder.read_info()?;
+		let mut data = vec![0; reader.output_buffer_size()];
+		let info = reader.next_frame(&mut data)?;
+		data.truncate(info.buffer_size());
+
+		let pixels = match info.color_type {
+			png::ColorType::Rgba => data,
+			png::ColorType::Rgb => data.chunks(3).flat_map(|c| [c[0], c[1], c[2], 255]).collect(),
+			png::ColorType::GrayscaleAlpha => data.chunks(2).flat_map(|c| [c[0], c[0], c[0], c[1]]).collect(),
+			png::ColorType::Grayscale => data.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+			png::ColorType::Indexed => return Err(anyhow::format_err!("Unexpanded palette in '{}'", path.display())),
+		};
+		Ok(Self {
+			width: info.width,
+			height: info.height,
+			pixels,
+		})
+	}
+
+	pub fn save_png(&self, path: &Path) -> anyhow::Result<()> {
+		let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+		encoder.set_color(png::ColorType::Rgba);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.write_header()?.write_image_data(&self.pixels)?;
+		Ok(())
+	}
+}
+
+/// Renders canvases offscreen, for export, thumbnails and tests
+pub struct HeadlessRenderer {
+	pub graphics: Graphics,
+	backend: Box<dyn CanvasBackend>,
+	/// Passes the changes to the strokes on to the backend
+	strokes: StrokeSync,
+}
+
+impl HeadlessRenderer {
+	pub fn new(backend: BackendKind) -> anyhow::Result<Self> {
+		let graphics = Graphics::headless()?;
+		let backend = canvas::create_backend(backend, &graphics, 1, 1)?;
+		Ok(Self {
+			graphics,
+			backend,
+			strokes: StrokeSync::default(),
+		})
+	}
+
+	/// Switch to another backend on the same device
+	pub fn set_backend(&mut self, backend: BackendKind) -> anyhow::Result<()> {
+		if backend != self.backend.kind() {
+			self.backend = canvas::create_backend(backend, &self.graphics, 1, 1)?;
+			self.strokes = StrokeSync::default();
+		}
+		Ok(())
+	}
+
+	/// Render the view of a widget at its size and wait for the GPU to finish
+	pub fn render_to_texture(&mut self, widget: &CanvasWidget) -> anyhow::Result<()> {
+		let (width, height) = (widget.get_width(), widget.get_height());
+		let output = self.backend.output();
+		if (output.get_width(), output.get_height()) != (width, height) {
+			self.backend.resize(&self.graphics, width, height);
+		}
+		self.strokes.apply(&widget.canvas, &mut *self.backend);
+		self.backend.render(&self.graphics, widget)?;
+		self.graphics.device.poll(wgpu::Maintain::Wait);
+		Ok(())
+	}
+
+	/// Render the view of a widget at its size and read back the pixels
+	pub fn render(&mut self, widget: &CanvasWidget) -> anyhow::Result<RgbaImage> {
+		self.render_to_texture(widget)?;
+		let (width, height) = (widget.get_width(), widget.get_height());
+		read_texture(&self.graphics, self.backend.output().get_texture(), width, height)
+	}
+}
+
+/// Copy a texture with 4 bytes per pixel, such as an `Rgba8Unorm` one, back from the GPU
+pub fn read_texture(graphics: &Graphics, texture: &wgpu::Texture, width: u32, height: u32) -> anyhow::Result<RgbaImage> {
+	// Rows of a texture copy have to be aligned, so they are padded in the buffer and unpadded after mapping it
+	let row_size = width * 4;
+	let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+	let padded_row_size = (row_size + align - 1) / align * align;
+	let buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Readback Buffer"),
+		size: padded_row_size as u64 * height as u64,
+		usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+		mapped_at_creation: false,
+	});
+
+	let mut encoder = graphics
+		.device
+		.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Readback") });
+	encoder.copy_texture_to_buffer(
+		texture.as_image_copy(),
+		wgpu::ImageCopyBuffer {
+			buffer: &buffer,
+			layout: wgpu::ImageDataLayout {
+				offset: 0,
+				bytes_per_row: std::num::NonZeroU32::new(padded_row_size),
+				rows_per_image: None,
+			},
+		},
+		wgpu::Extent3d {
+			width,
+			height,
+			depth_or_array_layers: 1,
+		},
+	);
+	graphics.queue.submit(Some(encoder.finish()));
+
+	let slice = buffer.slice(..);
+	let (sender, receiver) = mpsc::channel();
+	slice.map_async(wgpu::MapMode::Read, move |result| {
+		let _ = sender.send(result);
+	});
+	graphics.device.poll(wgpu::Maintain::Wait);
+	receiver.recv()??;
+
+	let mut pixels = Vec::with_capacity((row_size * height) as usize);
+	for row in slice.get_mapped_range().chunks(padded_row_size as usize) {
+		pixels.extend_from_slice(&row[..row_size as usize]);
+	}
+	buffer.unmap();
+
+	Ok(RgbaImage { width, height, pixels })
+}
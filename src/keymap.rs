@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use serde::{de::IntoDeserializer, Deserialize, Deserializer};
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+/// Something the user can trigger from the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+	Undo,
+	Redo,
+	PenTool,
+	EraserTool,
+	LassoTool,
+	PanTool,
+	LineTool,
+	RectangleTool,
+	EllipseTool,
+	/// Use the pan tool for as long as the key is held
+	HoldPan,
+	ZoomIn,
+	ZoomOut,
+	RotateLeft,
+	RotateRight,
+	ResetRotation,
+	ResetView,
+	/// Switch to the next background pattern
+	CycleBackground,
+	/// Turn snapping shapes and moved selections to the background pattern on or off
+	ToggleGridSnap,
+	/// Save the document. Not implemented yet, so this only logs a warning.
+	Save,
+}
+
+/// What a key chord in the config is bound to: an action, or `"unbound"` to remove the default binding of the chord
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBinding {
+	Action(Action),
+	Unbound,
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let name = String::deserialize(deserializer)?;
+		if name == "unbound" {
+			return Ok(Self::Unbound);
+		}
+		Action::deserialize(name.as_str().into_deserializer()).map(Self::Action)
+	}
+}
+
+/// A key pressed together with a set of modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+	pub modifiers: ModifiersState,
+	pub key: VirtualKeyCode,
+}
+
+impl KeyChord {
+	pub fn new(modifiers: ModifiersState, key: VirtualKeyCode) -> Self {
+		Self { modifiers, key }
+	}
+
+	/// Parse a chord like `"Ctrl+Shift+Z"`. The key is named as in winit's `VirtualKeyCode`.
+	pub fn parse(chord: &str) -> anyhow::Result<Self> {
+		let mut parts: Vec<&str> = chord.split('+').map(str::trim).collect();
+		let key = parts.pop().filter(|key| !key.is_empty());
+		let key = key.ok_or_else(|| anyhow::format_err!("Key chord '{chord}' has no key"))?;
+
+		let mut modifiers = ModifiersState::empty();
+		for modifier in parts {
+			modifiers |= match modifier.to_ascii_lowercase().as_str() {
+				"ctrl" | "control" => ModifiersState::CTRL,
+				"shift" => ModifiersState::SHIFT,
+				"alt" => ModifiersState::ALT,
+				"logo" | "super" | "cmd" => ModifiersState::LOGO,
+				_ => return Err(anyhow::format_err!("Unknown modifier '{modifier}' in key chord '{chord}'")),
+			};
+		}
+
+		let key = VirtualKeyCode::deserialize(key.into_deserializer())
+			.map_err(|_: serde::de::value::Error| anyhow::format_err!("Unknown key '{key}' in key chord '{chord}'"))?;
+
+		Ok(Self { modifiers, key })
+	}
+}
+
+/// Resolves key chords to actions
+pub struct Keymap {
+	bindings: HashMap<KeyChord, Action>,
+}
+
+impl Keymap {
+	/// Build the default keymap with the bindings from the config applied on top
+	pub fn new(overrides: &HashMap<String, KeyBinding>) -> anyhow::Result<Self> {
+		let mut keymap = Self::default();
+		for (chord, &binding) in overrides {
+			let chord = KeyChord::parse(chord)?;
+			match binding {
+				KeyBinding::Action(action) => keymap.bind(chord, action),
+				KeyBinding::Unbound => keymap.unbind(chord),
+			}
+		}
+		Ok(keymap)
+	}
+
+	pub fn bind(&mut self, chord: KeyChord, action: Action) {
+		self.bindings.insert(chord, action);
+	}
+
+	pub fn unbind(&mut self, chord: KeyChord) {
+		self.bindings.remove(&chord);
+	}
+
+	pub fn resolve(&self, modifiers: ModifiersState, key: VirtualKeyCode) -> Option<Action> {
+		self.bindings.get(&KeyChord::new(modifiers, key)).copied()
+	}
+}
+
+impl Default for Keymap {
+	fn default() -> Self {
+		use Action::*;
+		use VirtualKeyCode as Key;
+
+		let none = ModifiersState::empty();
+		let ctrl = ModifiersState::CTRL;
+		let shift = ModifiersState::SHIFT;
+		let ctrl_shift = ModifiersState::CTRL | ModifiersState::SHIFT;
+
+		let bindings = [
+			(ctrl, Key::Z, Undo),
+			(ctrl_shift, Key::Z, Redo),
+			(ctrl, Key::Y, Redo),
+			(none, Key::B, PenTool),
+			(none, Key::E, EraserTool),
+			(none, Key::L, LassoTool),
+			(none, Key::H, PanTool),
+			(none, Key::N, LineTool),
+			(none, Key::R, RectangleTool),
+			(none, Key::O, EllipseTool),
+			(none, Key::Space, HoldPan),
+			(none, Key::Equals, ZoomIn),
+			(ctrl, Key::Equals, ZoomIn),
+			(none, Key::Minus, ZoomOut),
+			(ctrl, Key::Minus, ZoomOut),
+			(none, Key::Key4, RotateLeft),
+			(none, Key::Key6, RotateRight),
+			(none, Key::Key5, ResetRotation),
+			(ctrl, Key::Key0, ResetView),
+			(none, Key::G, CycleBackground),
+			(shift, Key::G, ToggleGridSnap),
+			(ctrl, Key::S, Save),
+		];
+
+		Self {
+			bindings: bindings
+				.into_iter()
+				.map(|(modifiers, key, action)| (KeyChord::new(modifiers, key), action))
+				.collect(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn chords_parse_modifiers_and_keys() {
+		let ctrl_shift = ModifiersState::CTRL | ModifiersState::SHIFT;
+		assert_eq!(
+			KeyChord::parse("Ctrl+Shift+Z").unwrap(),
+			KeyChord::new(ctrl_shift, VirtualKeyCode::Z)
+		);
+		assert_eq!(
+			KeyChord::parse(" shift + control +Z").unwrap(),
+			KeyChord::new(ctrl_shift, VirtualKeyCode::Z)
+		);
+		assert_eq!(
+			KeyChord::parse("Cmd+Alt+Key0").unwrap(),
+			KeyChord::new(ModifiersState::LOGO | ModifiersState::ALT, VirtualKeyCode::Key0)
+		);
+		assert_eq!(
+			KeyChord::parse("Space").unwrap(),
+			KeyChord::new(ModifiersState::empty(), VirtualKeyCode::Space)
+		);
+
+		for chord in ["", "Ctrl+", "Hyper+Z", "Ctrl+Nope", "Ctrl+z"] {
+			assert!(KeyChord::parse(chord).is_err(), "'{chord}' parsed");
+		}
+	}
+
+	#[test]
+	fn chords_resolve_with_exactly_their_modifiers() {
+		let keymap = Keymap::default();
+		let ctrl = ModifiersState::CTRL;
+		assert_eq!(keymap.resolve(ctrl, VirtualKeyCode::Z), Some(Action::Undo));
+		assert_eq!(
+			keymap.resolve(ctrl | ModifiersState::SHIFT, VirtualKeyCode::Z),
+			Some(Action::Redo)
+		);
+		assert_eq!(keymap.resolve(ModifiersState::empty(), VirtualKeyCode::Z), None);
+		assert_eq!(keymap.resolve(ModifiersState::ALT, VirtualKeyCode::B), None);
+		assert_eq!(
+			keymap.resolve(ModifiersState::empty(), VirtualKeyCode::B),
+			Some(Action::PenTool)
+		);
+		assert_eq!(keymap.resolve(ctrl, VirtualKeyCode::S), Some(Action::Save));
+	}
+
+	#[test]
+	fn config_overrides_rebind_and_unbind_chords() {
+		let overrides: HashMap<String, KeyBinding> = toml::from_str(
+			r#"
+				"Ctrl+Z" = "redo"
+				"Alt+P" = "pen_tool"
+				"Ctrl+Y" = "unbound"
+				"Ctrl+Q" = "unbound"
+			"#,
+		)
+		.unwrap();
+		let keymap = Keymap::new(&overrides).unwrap();
+		let ctrl = ModifiersState::CTRL;
+		assert_eq!(keymap.resolve(ctrl, VirtualKeyCode::Z), Some(Action::Redo));
+		assert_eq!(keymap.resolve(ModifiersState::ALT, VirtualKeyCode::P), Some(Action::PenTool));
+		assert_eq!(keymap.resolve(ctrl, VirtualKeyCode::Y), None);
+		assert_eq!(keymap.resolve(ctrl, VirtualKeyCode::Q), None);
+		// Defaults that weren't overridden stay
+		assert_eq!(
+			keymap.resolve(ctrl | ModifiersState::SHIFT, VirtualKeyCode::Z),
+			Some(Action::Redo)
+		);
+
+		assert!(toml::from_str::<HashMap<String, KeyBinding>>(r#""Ctrl+Z" = "fly""#).is_err());
+		let bad_chord = HashMap::from([("Ctrl+Nope".to_string(), KeyBinding::Unbound)]);
+		assert!(Keymap::new(&bad_chord).is_err());
+	}
+}
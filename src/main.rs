@@ -1,5 +1,10 @@
 #![allow(unused_imports)]
 
+use std::{
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+
 use blit::BlitPipeline;
 use derive_more::From;
 use linalg::{
@@ -9,99 +14,257 @@ use linalg::{
 use ui::Ui;
 use util::{timeit, VelloToLin};
 use vello::kurbo::{Affine, Point};
+use vello::peniko::Color;
 use wgpu::{Device, Instance, Queue, RenderPipeline, TextureFormat};
-use winit::event::Tablet;
+use winit::event::{DeviceId, Tablet, TabletTool};
 use winit::{
 	dpi::{LogicalSize, PhysicalPosition},
-	event::{ElementState, Event, MouseButton, MouseScrollDelta, Touch, TouchPhase, WindowEvent},
-	event_loop::{ControlFlow, EventLoop},
+	event::{
+		ElementState, Event, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, Touch, TouchPhase, VirtualKeyCode,
+		WindowEvent,
+	},
+	event_loop::{ControlFlow, EventLoop, EventLoopBuilder},
 	window::{Window, WindowBuilder},
 };
 
+use bench::BenchOptions;
 use canvas::*;
+use config::Config;
+use editor::{Editor, Redraw};
+use gpu::{GpuConfig, PowerPreference, PresentMode, WgpuBackend};
+use headless::HeadlessRenderer;
+use record::{InputEvent, RecordedEvent, Recorder};
 
+pub mod bench;
 pub mod blit;
 pub mod canvas;
+pub mod config;
+pub mod cursor;
+pub mod editor;
+pub mod gesture;
+pub mod gpu;
+pub mod headless;
+pub mod keymap;
+pub mod palm;
 pub mod pen;
+pub mod record;
+pub mod shader;
+pub mod tablet;
+pub mod tool;
 pub mod ui;
 pub mod util;
 
 pub struct Graphics {
 	instance: Instance,
+	adapter: wgpu::Adapter,
+	adapter_info: wgpu::AdapterInfo,
 	device: Device,
 	queue: Queue,
 }
 
-struct InputState {
-	mouse_press: Option<MouseButton>,
-	pen_press: bool,
-}
-
-impl InputState {
-	pub fn new() -> Self {
-		Self {
-			mouse_press: None,
-			pen_press: false,
+impl Graphics {
+	/// Open a device, along with a surface for `window` if there is one. The APIs and adapters are tried in the order
+	/// given by `GpuConfig::attempts` until one works.
+	pub fn new(config: &GpuConfig, window: Option<&Window>) -> anyhow::Result<(Self, Option<wgpu::Surface>)> {
+		for (backends, force_fallback_adapter) in config.attempts() {
+			let instance = Instance::new(backends);
+			let surface = window.map(|window| unsafe { instance.create_surface(window) });
+			let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+				power_preference: config.power_preference.into(),
+				force_fallback_adapter,
+				compatible_surface: surface.as_ref(),
+			}));
+			let adapter = match adapter {
+				Some(adapter) => adapter,
+				None => {
+					log::info!("No graphics adapter for {backends:?} (fallback adapter: {force_fallback_adapter})");
+					continue;
+				}
+			};
+			match Self::from_adapter(instance, adapter) {
+				Ok(graphics) => return Ok((graphics, surface)),
+				Err(e) => log::warn!("Failed to open a device for {backends:?}: {e:#}"),
+			}
 		}
+		Err(anyhow::format_err!("Failed to find a graphics adapter"))
 	}
 
-	pub fn handle_mouse_pressed(&mut self, button: MouseButton) -> bool {
-		if self.mouse_press.is_none() {
-			self.mouse_press = Some(button);
-			true
-		} else {
-			false
-		}
+	/// Open a device for rendering without a window. Software adapters such as lavapipe are used when there is no
+	/// GPU, so this also works on CI machines.
+	pub fn headless() -> anyhow::Result<Self> {
+		let config = GpuConfig {
+			backend: WgpuBackend::Any,
+			..Default::default()
+		};
+		Self::new(&config, None).map(|(graphics, _)| graphics)
 	}
 
-	pub fn handle_mouse_released(&mut self, button: MouseButton) -> bool {
-		if self.mouse_press == Some(button) {
-			self.mouse_press = None;
-			true
-		} else {
-			false
-		}
+	fn from_adapter(instance: Instance, adapter: wgpu::Adapter) -> anyhow::Result<Self> {
+		let adapter_info = adapter.get_info();
+		log::info!(
+			"Using {} ({:?}, {:?})",
+			adapter_info.name,
+			adapter_info.backend,
+			adapter_info.device_type
+		);
+		let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))?;
+		Ok(Self {
+			instance,
+			adapter,
+			adapter_info,
+			device,
+			queue,
+		})
 	}
+}
 
-	pub fn is_mouse_dragging(&self, button: MouseButton) -> bool {
-		self.mouse_press == Some(button)
-	}
+struct App {
+	ui: Ui,
+	editor: Editor,
+	/// Winit devices in the order they were first seen, so recordings can refer to them by index
+	devices: Vec<DeviceId>,
+	recorder: Option<Recorder>,
+	/// When the replay started, if one is running. Live input is ignored during a replay.
+	replay_start: Option<Instant>,
+}
+
+/// Command line options
+#[derive(Debug, Default)]
+struct Args {
+	/// Write all input to this file
+	record: Option<PathBuf>,
+	/// Feed the input from this recording to the app instead of the devices
+	replay: Option<PathBuf>,
+	/// Render the document to this PNG without opening a window, after applying the replay if there is one
+	export: Option<PathBuf>,
+	/// Run the benchmarks instead of the app
+	bench: Option<BenchOptions>,
+	/// Renderer for the canvas, overriding the config
+	backend: Option<BackendKind>,
+	/// Adapter and presentation settings overriding the config and environment
+	wgpu_backend: Option<WgpuBackend>,
+	power_preference: Option<PowerPreference>,
+	force_fallback_adapter: bool,
+	present_mode: Option<PresentMode>,
+}
 
-	pub fn handle_pen_pressed(&mut self) -> bool {
-		!std::mem::replace(&mut self.pen_press, true)
+impl Args {
+	fn parse() -> anyhow::Result<Self> {
+		let mut args = Self::default();
+		let mut iter = std::env::args_os().skip(1);
+		while let Some(arg) = iter.next() {
+			let mut value = || {
+				iter.next()
+					.map(PathBuf::from)
+					.ok_or_else(|| anyhow::format_err!("Missing value for {}", arg.to_string_lossy()))
+			};
+			match arg.to_str() {
+				Some("--record") => args.record = Some(value()?),
+				Some("--replay") => args.replay = Some(value()?),
+				Some("--export") => args.export = Some(value()?),
+				Some("--backend") => args.backend = Some(BackendKind::parse(&value()?.to_string_lossy())?),
+				Some("--wgpu-backend") => {
+					args.wgpu_backend = Some(gpu::parse_setting("wgpu backend", &value()?.to_string_lossy())?)
+				}
+				Some("--power-preference") => {
+					args.power_preference = Some(gpu::parse_setting("power preference", &value()?.to_string_lossy())?)
+				}
+				Some("--force-fallback-adapter") => args.force_fallback_adapter = true,
+				Some("--present-mode") => {
+					args.present_mode = Some(gpu::parse_setting("present mode", &value()?.to_string_lossy())?)
+				}
+				Some("--bench") => {
+					args.bench_options();
+				}
+				Some("--bench-sizes") => {
+					let list = value()?.to_string_lossy().into_owned();
+					args.bench_options().board_sizes = list
+						.split(',')
+						.map(|size| size.trim().parse())
+						.collect::<Result<_, _>>()
+						.map_err(|e| anyhow::format_err!("Invalid board sizes '{list}': {e}"))?;
+				}
+				Some("--bench-output") => args.bench_options().output = Some(value()?),
+				Some("--bench-baseline") => args.bench_options().baseline = Some(value()?),
+				_ => return Err(anyhow::format_err!("Unknown argument '{}'", arg.to_string_lossy())),
+			}
+		}
+		Ok(args)
 	}
 
-	pub fn handle_pen_released(&mut self) -> bool {
-		!std::mem::replace(&mut self.pen_press, false)
+	/// Pick the rendering backend from the arguments, then the config, then what is compiled in
+	fn backend(&self, config: &Config) -> anyhow::Result<BackendKind> {
+		match self.backend.or(config.backend) {
+			Some(backend) => Ok(backend),
+			None => BackendKind::preferred(),
+		}
 	}
 
-	pub fn is_pen_pressed(&self) -> bool {
-		self.pen_press
+	/// Get the adapter settings from the config, overridden by the environment and then the arguments
+	fn gpu_config(&self, config: &Config) -> anyhow::Result<GpuConfig> {
+		let mut gpu = config.gpu.clone();
+		gpu.apply_env()?;
+		gpu.backend = self.wgpu_backend.unwrap_or(gpu.backend);
+		gpu.power_preference = self.power_preference.unwrap_or(gpu.power_preference);
+		gpu.force_fallback_adapter |= self.force_fallback_adapter;
+		gpu.present_mode = self.present_mode.unwrap_or(gpu.present_mode);
+		log::info!("GPU settings: {gpu:?}");
+		Ok(gpu)
 	}
-}
 
-struct App {
-	ui: Ui,
-	old_mouse_pos: Point2,
-	input_state: InputState,
-	current_pressure: f32,
-	zoom_level: i32,
+	/// Get the benchmark options, enabling benchmarks if any `--bench` argument is given
+	fn bench_options(&mut self) -> &mut BenchOptions {
+		self.bench.get_or_insert_with(|| BenchOptions {
+			board_sizes: bench::DEFAULT_BOARD_SIZES.to_vec(),
+			..Default::default()
+		})
+	}
 }
 
 impl App {
-	pub fn new(event_loop: &mut EventLoop<()>) -> anyhow::Result<Self> {
-		let ui = timeit!("ui init", Ui::new(event_loop)?);
+	pub fn new(event_loop: &mut EventLoop<RecordedEvent>, args: &Args) -> anyhow::Result<Self> {
+		let config = Config::load()?;
+		let ui = timeit!(
+			"ui init",
+			Ui::new(event_loop, args.backend(&config)?, &args.gpu_config(&config)?)?
+		);
+		let (width, height) = ui.size();
+		let mut editor = Editor::new(config, width, height)?;
+		editor.set_scale_factor(ui.scale_factor());
+		let mut recorder = args.record.as_deref().map(Recorder::create).transpose()?;
+		if let Some(recorder) = &mut recorder {
+			// Start the recording from the view the editor has, so a replay maps positions to the page the same way
+			let now = Instant::now();
+			recorder.record(now, InputEvent::Resized { width, height })?;
+			recorder.record(
+				now,
+				InputEvent::ScaleFactorChanged {
+					scale_factor: ui.scale_factor(),
+				},
+			)?;
+		}
+
+		let replay_start = match &args.replay {
+			Some(path) => {
+				let events = record::load_recording(path)?;
+				log::info!("Replaying {} events from '{}'", events.len(), path.display());
+				let start = Instant::now();
+				record::spawn_replay(events, start, event_loop.create_proxy());
+				Some(start)
+			}
+			None => None,
+		};
 
 		Ok(Self {
 			ui,
-			old_mouse_pos: Point2::new(0.0, 0.0),
-			input_state: InputState::new(),
-			current_pressure: 0.0,
-			zoom_level: 0,
+			editor,
+			devices: Vec::new(),
+			recorder,
+			replay_start,
 		})
 	}
 
-	pub fn run(mut self, event_loop: EventLoop<()>) -> anyhow::Result<()> {
+	pub fn run(mut self, event_loop: EventLoop<RecordedEvent>) -> anyhow::Result<()> {
 		event_loop.run(move |event, _target, control| match self.handle_event(event, control) {
 			Ok(()) => {}
 			Err(e) => {
@@ -111,37 +274,52 @@ impl App {
 		});
 	}
 
-	fn handle_event(&mut self, event: Event<()>, control: &mut ControlFlow) -> anyhow::Result<()> {
-		*control = ControlFlow::Wait;
+	fn handle_event(&mut self, event: Event<RecordedEvent>, control: &mut ControlFlow) -> anyhow::Result<()> {
+		*control = match self.ui.next_shader_poll() {
+			Some(deadline) => ControlFlow::WaitUntil(deadline),
+			None => ControlFlow::Wait,
+		};
 
 		match event {
+			Event::NewEvents(_) => {
+				self.ui.poll_shaders();
+				if let Some(deadline) = self.ui.next_shader_poll() {
+					*control = ControlFlow::WaitUntil(deadline);
+				}
+			}
 			Event::WindowEvent { window_id: _, event } => match event {
 				WindowEvent::CloseRequested => *control = ControlFlow::Exit,
-				WindowEvent::MouseInput {
-					device_id: _,
-					state,
-					button,
-					..
-				} => self.handle_mouse_button(state, button),
-				WindowEvent::CursorMoved {
-					device_id: _, position, ..
-				} => self.handle_mouse_moved(Point2::new(position.x, position.y)),
-				WindowEvent::MouseWheel {
-					device_id: _,
-					delta,
-					phase,
-					..
-				} => self.handle_mouse_wheel(delta, phase),
-				WindowEvent::Touch(touch) => self.handle_touch(touch),
-				WindowEvent::Tablet(tablet) => self.handle_tablet(tablet),
-				WindowEvent::Resized(_size) => {
-					self.ui.handle_window_resize();
+				WindowEvent::Resized(size) => {
+					self.ui.handle_window_resize(size);
+					self.handle_view_resize()?;
+				}
+				WindowEvent::ScaleFactorChanged {
+					scale_factor,
+					new_inner_size,
+				} => {
+					log::info!("Scale factor changed to {scale_factor}");
+					self.ui.handle_window_resize(*new_inner_size);
+					self.handle_view_resize()?;
+					if self.replay_start.is_none() {
+						self.handle_input(InputEvent::ScaleFactorChanged { scale_factor }, Instant::now())?;
+					}
+				}
+				event => {
+					if let Some(input) = self.translate(event) {
+						if self.replay_start.is_none() {
+							self.handle_input(input, Instant::now())?;
+						}
+					}
 				}
-				_ => {}
 			},
+			Event::UserEvent(recorded) => {
+				// Replayed events carry their original timing so time-based filtering behaves as it did when recording
+				let start = self.replay_start.unwrap_or_else(Instant::now);
+				self.handle_input(recorded.event, start + Duration::from_secs_f64(recorded.time.max(0.0)))?;
+			}
 			Event::RedrawRequested(_window_id) => {
-				self.ui.canvas.render(&self.ui.graphics);
-				self.ui.present()?;
+				self.ui.cursor = self.editor.cursor();
+				self.ui.render(&self.editor.canvas)?;
 			}
 			_ => {}
 		};
@@ -149,88 +327,144 @@ impl App {
 		Ok(())
 	}
 
-	fn handle_mouse_button(&mut self, state: ElementState, button: MouseButton) {
-		if state == ElementState::Pressed {
-			let drag_start = self.input_state.handle_mouse_pressed(button);
+	/// Convert a window event to the input the app handles, if it is any
+	fn translate(&mut self, event: WindowEvent) -> Option<InputEvent> {
+		Some(match event {
+			WindowEvent::MouseInput { state, button, .. } => InputEvent::MouseButton {
+				button,
+				pressed: state == ElementState::Pressed,
+			},
+			WindowEvent::CursorMoved { position, .. } => InputEvent::MouseMoved {
+				x: position.x,
+				y: position.y,
+			},
+			WindowEvent::CursorLeft { .. } => InputEvent::MouseLeft,
+			WindowEvent::MouseWheel { delta, .. } => InputEvent::Wheel { delta },
+			WindowEvent::KeyboardInput { input, .. } => InputEvent::Key {
+				key: input.virtual_keycode?,
+				pressed: input.state == ElementState::Pressed,
+			},
+			WindowEvent::ModifiersChanged(modifiers) => InputEvent::Modifiers { modifiers },
+			WindowEvent::Touch(touch) => InputEvent::Touch {
+				id: touch.id,
+				phase: touch.phase,
+				x: touch.location.x,
+				y: touch.location.y,
+				force: touch.force.map(|force| force.normalized()),
+			},
+			WindowEvent::Tablet(tablet) => match tablet {
+				Tablet::Down => InputEvent::PenDown,
+				Tablet::Up => InputEvent::PenUp,
+				Tablet::Motion(pos) => InputEvent::PenMoved { x: pos.x, y: pos.y },
+				Tablet::Pressure(pressure) => InputEvent::PenPressure {
+					pressure: pressure.normalized() as f32,
+				},
+				Tablet::ProximityIn { device_id } => InputEvent::PenProximity {
+					device: self.device_index(device_id),
+					near: true,
+				},
+				Tablet::ProximityOut { device_id } => InputEvent::PenProximity {
+					device: self.device_index(device_id),
+					near: false,
+				},
+				Tablet::Tool { device_id, tool } => InputEvent::PenTool {
+					device: self.device_index(device_id),
+					eraser: matches!(tool, TabletTool::Eraser),
+				},
+				Tablet::Button {
+					device_id,
+					button,
+					state,
+				} => InputEvent::PenButton {
+					device: self.device_index(device_id),
+					button,
+					pressed: state == ElementState::Pressed,
+				},
+				_ => return None,
+			},
+			_ => return None,
+		})
+	}
 
-			if button == MouseButton::Left && drag_start {
-				self.ui.canvas.canvas.start_stroke();
-				self.ui.window.request_redraw();
+	fn device_index(&mut self, device_id: DeviceId) -> u32 {
+		let index = match self.devices.iter().position(|&other| other == device_id) {
+			Some(index) => index,
+			None => {
+				self.devices.push(device_id);
+				self.devices.len() - 1
 			}
-		} else if state == ElementState::Released {
-			let drag_end = self.input_state.handle_mouse_released(button);
+		};
+		index as u32
+	}
 
-			if button == MouseButton::Left && drag_end {
-				self.ui.canvas.canvas.end_stroke();
-				self.ui.window.request_redraw();
-			}
+	/// Pass the new size of the window on to the editor. During a replay the editor keeps the size from the recording,
+	/// so positions map to the page as they did when recording.
+	fn handle_view_resize(&mut self) -> anyhow::Result<()> {
+		if self.replay_start.is_some() {
+			self.ui.invalidate_canvas();
+			return Ok(());
 		}
+		let (width, height) = self.ui.size();
+		self.handle_input(InputEvent::Resized { width, height }, Instant::now())
 	}
 
-	fn handle_mouse_moved(&mut self, position: Point2) {
-		let widget_delta = position - self.old_mouse_pos;
-		let delta = self.ui.canvas.transform() * widget_delta;
-
-		if self.input_state.is_mouse_dragging(MouseButton::Left) {
-			self.ui.canvas.canvas.move_stroke(self.ui.canvas.transform() * position, 1.0);
-			self.ui.window.request_redraw();
-		} else if self.input_state.is_mouse_dragging(MouseButton::Middle) {
-			self.ui.canvas.pan -= delta;
-			self.ui.window.request_redraw();
+	/// Handle live or replayed input that happened at `time`
+	fn handle_input(&mut self, input: InputEvent, time: Instant) -> anyhow::Result<()> {
+		if let Some(recorder) = &mut self.recorder {
+			recorder.record(time, input)?;
 		}
 
-		self.old_mouse_pos = position;
+		self.editor.handle_input(input, time);
+		self.apply_redraw();
+		Ok(())
 	}
 
-	fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta, _phase: TouchPhase) {
-		match delta {
-			MouseScrollDelta::LineDelta(_h, v) => {
-				if v > 0.0 {
-					self.zoom_level += 1;
-				} else if v < 0.0 {
-					self.zoom_level -= 1;
-				}
-				self.ui.canvas.zoom = 2.0f64.powi(self.zoom_level);
-
-				self.ui.window.request_redraw();
-			}
-			MouseScrollDelta::PixelDelta(_) => todo!(),
+	/// Schedule whatever the editor changed to be drawn
+	fn apply_redraw(&mut self) {
+		match self.editor.take_redraw() {
+			Redraw::None => {}
+			Redraw::Overlay => self.ui.window.request_redraw(),
+			Redraw::Canvas => self.ui.invalidate_canvas(),
 		}
 	}
+}
 
-	fn handle_touch(&mut self, _touch: Touch) {}
-
-	fn handle_tablet(&mut self, tablet: Tablet) {
-		match tablet {
-			Tablet::Down => {
-				self.input_state.handle_pen_pressed();
-
-				self.ui.canvas.canvas.start_stroke();
-				self.ui.window.request_redraw();
-			}
-			Tablet::Up => {
-				self.input_state.handle_pen_released();
-
-				self.ui.canvas.canvas.end_stroke();
-				self.ui.window.request_redraw();
-			}
-			Tablet::Motion(pos) => {
-				if self.input_state.is_pen_pressed() {
-					self.ui
-						.canvas
-						.canvas
-						.move_stroke(self.ui.canvas.transform() * Point2::new(pos.x, pos.y), self.current_pressure);
-					self.ui.window.request_redraw();
-				}
-			}
-			Tablet::Pressure(pressure) => self.current_pressure = pressure.normalized() as f32,
-			_ => {}
+/// Size of the view that replays are run through when exporting
+const EXPORT_VIEW_SIZE: (u32, u32) = (1024, 768);
+/// Space around the strokes of an exported document, in pixels
+const EXPORT_MARGIN: f64 = 32.0;
+
+/// Replay a recording, if any, as fast as possible and render the resulting document to a PNG
+fn export(args: &Args, path: &Path) -> anyhow::Result<()> {
+	let (width, height) = EXPORT_VIEW_SIZE;
+	let config = Config::load()?;
+	let backend = args.backend(&config)?;
+	let mut editor = Editor::new(config, width, height)?;
+	if let Some(replay) = &args.replay {
+		let start = Instant::now();
+		for recorded in record::load_recording(replay)? {
+			editor.handle_input(recorded.event, start + Duration::from_secs_f64(recorded.time.max(0.0)));
 		}
 	}
+
+	editor.canvas.fit_to_content(EXPORT_MARGIN);
+	let mut renderer = HeadlessRenderer::new(backend)?;
+	let image = renderer.render(&editor.canvas)?;
+	image.save_png(path)?;
+	log::info!("Exported {}x{} image to '{}'", image.width, image.height, path.display());
+	Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
 	env_logger::init();
-	let mut event_loop = EventLoop::new();
-	App::new(&mut event_loop).and_then(|app| app.run(event_loop))
+	let args = Args::parse()?;
+	if let Some(options) = &args.bench {
+		return bench::run(options, args.backend(&Config::load()?)?);
+	}
+	if let Some(path) = &args.export {
+		return export(&args, path);
+	}
+
+	let mut event_loop = EventLoopBuilder::with_user_event().build();
+	App::new(&mut event_loop, &args).and_then(|app| app.run(event_loop))
 }
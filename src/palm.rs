@@ -0,0 +1,208 @@
+use std::{
+	collections::HashSet,
+	time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use winit::event::TouchPhase;
+
+/// Rules for ignoring touches from a palm resting on the screen, read from the `[palm_rejection]` section of the config.
+///
+/// There is no rule for large contact areas, since winit reports no contact size for touches on any platform.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PalmRejectionConfig {
+	/// Ignore touches while a pen is in proximity of the screen or touching it
+	pub while_pen_near: bool,
+	/// How long after the pen leaves proximity or is lifted touches are still ignored, in milliseconds
+	pub pen_grace_ms: u64,
+	/// Ignore touches pressing harder than this normalized force. Touches without a force, like those on most X11 and
+	/// Wayland setups, are never rejected by this rule.
+	pub max_force: Option<f64>,
+}
+
+impl Default for PalmRejectionConfig {
+	fn default() -> Self {
+		Self {
+			while_pen_near: true,
+			pen_grace_ms: 500,
+			max_force: None,
+		}
+	}
+}
+
+/// What to do with a touch event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchFilter {
+	Accept,
+	Reject,
+	/// The touch was accepted so far but is rejected from now on, so whatever it started should be cancelled
+	Cancel,
+}
+
+/// Decides which touches come from a palm. Once a touch is rejected, the rest of its events are too.
+pub struct PalmRejector {
+	pub config: PalmRejectionConfig,
+	/// Pens in proximity, by device number
+	pens_near: HashSet<u32>,
+	pen_pressed: bool,
+	/// When a pen last left proximity or was lifted
+	pen_left: Option<Instant>,
+	rejected: HashSet<u64>,
+}
+
+impl PalmRejector {
+	pub fn new(config: PalmRejectionConfig) -> Self {
+		Self {
+			config,
+			pens_near: HashSet::new(),
+			pen_pressed: false,
+			pen_left: None,
+			rejected: HashSet::new(),
+		}
+	}
+
+	pub fn handle_pen_proximity(&mut self, device: u32, near: bool, now: Instant) {
+		if near {
+			self.pens_near.insert(device);
+		} else if self.pens_near.remove(&device) {
+			self.pen_left = Some(now);
+		}
+	}
+
+	/// Record the pen touching the screen or being lifted. This also covers platforms that report no proximity.
+	pub fn handle_pen_pressed(&mut self, pressed: bool, now: Instant) {
+		if self.pen_pressed && !pressed {
+			self.pen_left = Some(now);
+		}
+		self.pen_pressed = pressed;
+	}
+
+	fn is_pen_near(&self, now: Instant) -> bool {
+		let grace = Duration::from_millis(self.config.pen_grace_ms);
+		self.config.while_pen_near
+			&& (self.pen_pressed
+				|| !self.pens_near.is_empty()
+				|| self
+					.pen_left
+					.map_or(false, |left| now.saturating_duration_since(left) <= grace))
+	}
+
+	fn is_palm_force(&self, force: Option<f64>) -> bool {
+		matches!((force, self.config.max_force), (Some(force), Some(max)) if force > max)
+	}
+
+	pub fn filter(&mut self, id: u64, phase: TouchPhase, force: Option<f64>, now: Instant) -> TouchFilter {
+		let ended = matches!(phase, TouchPhase::Ended | TouchPhase::Cancelled);
+
+		if self.rejected.contains(&id) {
+			if ended {
+				self.rejected.remove(&id);
+			}
+			return TouchFilter::Reject;
+		}
+
+		if !ended && (self.is_pen_near(now) || self.is_palm_force(force)) {
+			self.rejected.insert(id);
+			return match phase {
+				TouchPhase::Started => TouchFilter::Reject,
+				_ => TouchFilter::Cancel,
+			};
+		}
+
+		TouchFilter::Accept
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn touches_are_rejected_while_a_pen_hovers_and_shortly_after_it_leaves() {
+		let mut rejector = PalmRejector::new(PalmRejectionConfig::default());
+		let start = Instant::now();
+		let at = |ms| start + Duration::from_millis(ms);
+		rejector.handle_pen_proximity(0, true, start);
+		rejector.handle_pen_proximity(1, true, start);
+
+		// Hovering without moving sends nothing, which must not end the rejection
+		assert_eq!(rejector.filter(1, TouchPhase::Started, None, at(10_000)), TouchFilter::Reject);
+
+		// Another pen is still near
+		rejector.handle_pen_proximity(1, false, at(10_000));
+		assert_eq!(rejector.filter(2, TouchPhase::Started, None, at(20_000)), TouchFilter::Reject);
+
+		rejector.handle_pen_proximity(0, false, at(20_000));
+		assert_eq!(rejector.filter(3, TouchPhase::Started, None, at(20_500)), TouchFilter::Reject);
+		assert_eq!(rejector.filter(4, TouchPhase::Started, None, at(20_501)), TouchFilter::Accept);
+	}
+
+	#[test]
+	fn touches_are_rejected_while_the_pen_is_down_and_shortly_after_it_lifts() {
+		let mut rejector = PalmRejector::new(PalmRejectionConfig::default());
+		let start = Instant::now();
+		let at = |ms| start + Duration::from_millis(ms);
+		assert_eq!(rejector.filter(1, TouchPhase::Started, None, start), TouchFilter::Accept);
+
+		rejector.handle_pen_pressed(true, start);
+		assert_eq!(rejector.filter(2, TouchPhase::Started, None, at(10_000)), TouchFilter::Reject);
+		rejector.handle_pen_pressed(false, at(10_000));
+		assert_eq!(rejector.filter(3, TouchPhase::Started, None, at(10_500)), TouchFilter::Reject);
+		assert_eq!(rejector.filter(4, TouchPhase::Started, None, at(10_501)), TouchFilter::Accept);
+	}
+
+	#[test]
+	fn pens_are_ignored_when_the_rule_is_off() {
+		let mut rejector = PalmRejector::new(PalmRejectionConfig {
+			while_pen_near: false,
+			..Default::default()
+		});
+		let now = Instant::now();
+		rejector.handle_pen_proximity(0, true, now);
+		rejector.handle_pen_pressed(true, now);
+		assert_eq!(rejector.filter(1, TouchPhase::Started, None, now), TouchFilter::Accept);
+	}
+
+	#[test]
+	fn accepted_touches_are_cancelled_when_the_pen_comes_near() {
+		let mut rejector = PalmRejector::new(PalmRejectionConfig::default());
+		let start = Instant::now();
+		assert_eq!(rejector.filter(1, TouchPhase::Started, None, start), TouchFilter::Accept);
+
+		rejector.handle_pen_proximity(0, true, start);
+		assert_eq!(rejector.filter(1, TouchPhase::Moved, None, start), TouchFilter::Cancel);
+		assert_eq!(rejector.filter(1, TouchPhase::Moved, None, start), TouchFilter::Reject);
+	}
+
+	#[test]
+	fn ended_touches_are_forgotten() {
+		let mut rejector = PalmRejector::new(PalmRejectionConfig::default());
+		let start = Instant::now();
+		rejector.handle_pen_proximity(0, true, start);
+		assert_eq!(rejector.filter(1, TouchPhase::Started, None, start), TouchFilter::Reject);
+		assert_eq!(rejector.filter(1, TouchPhase::Ended, None, start), TouchFilter::Reject);
+
+		// Ending a touch is never rejected on its own, and the id can be reused once the pen is gone
+		assert_eq!(rejector.filter(2, TouchPhase::Ended, None, start), TouchFilter::Accept);
+		rejector.handle_pen_proximity(0, false, start);
+		let later = start + Duration::from_secs(1);
+		assert_eq!(rejector.filter(1, TouchPhase::Started, None, later), TouchFilter::Accept);
+	}
+
+	#[test]
+	fn hard_touches_are_rejected_when_a_force_limit_is_set() {
+		let mut rejector = PalmRejector::new(PalmRejectionConfig {
+			max_force: Some(0.8),
+			..Default::default()
+		});
+		let now = Instant::now();
+		assert_eq!(rejector.filter(1, TouchPhase::Started, Some(0.5), now), TouchFilter::Accept);
+		assert_eq!(rejector.filter(1, TouchPhase::Moved, Some(0.9), now), TouchFilter::Cancel);
+		assert_eq!(rejector.filter(2, TouchPhase::Started, Some(0.9), now), TouchFilter::Reject);
+		assert_eq!(rejector.filter(3, TouchPhase::Started, None, now), TouchFilter::Accept);
+
+		rejector.config.max_force = None;
+		assert_eq!(rejector.filter(4, TouchPhase::Started, Some(1.0), now), TouchFilter::Accept);
+	}
+}
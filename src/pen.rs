@@ -1,5 +1,8 @@
 use linalg::*;
 
+/// Width in page units of a stroke drawn at full pressure
+pub const STROKE_WIDTH: f32 = 8.0;
+
 #[derive(Debug, Clone, Copy)]
 pub struct PenEvent {
 	pub pos: Point2,
@@ -10,3 +13,8 @@ pub struct PenEvent {
 pub fn flat_pressure_curve(pressure: f32) -> f32 {
 	pressure
 }
+
+/// Get the width of the stroke segment between two samples, from their average pressure
+pub fn segment_width(a: &PenEvent, b: &PenEvent) -> f32 {
+	STROKE_WIDTH * flat_pressure_curve((a.pressure + b.pressure) * 0.5)
+}
@@ -0,0 +1,210 @@
+use std::{
+	fs::File,
+	io::{BufRead, BufReader, BufWriter, Write},
+	path::Path,
+	time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use winit::{
+	event::{ModifiersState, MouseButton, MouseScrollDelta, TouchPhase, VirtualKeyCode},
+	event_loop::EventLoopProxy,
+};
+
+/// Input as the app handles it, in a form that can be written to a recording and fed back later. Positions are in
+/// widget coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputEvent {
+	MouseButton {
+		button: MouseButton,
+		pressed: bool,
+	},
+	MouseMoved {
+		x: f64,
+		y: f64,
+	},
+	MouseLeft,
+	Wheel {
+		delta: MouseScrollDelta,
+	},
+	Touch {
+		id: u64,
+		phase: TouchPhase,
+		x: f64,
+		y: f64,
+		/// Normalized force, if the platform reports it
+		force: Option<f64>,
+	},
+	PenDown,
+	PenUp,
+	PenMoved {
+		x: f64,
+		y: f64,
+	},
+	PenPressure {
+		pressure: f32,
+	},
+	/// A pen came into or left the range the tablet senses it in. Devices are numbered as for `PenTool`.
+	PenProximity {
+		device: u32,
+		near: bool,
+	},
+	/// A pen switched between its tip and eraser end. Devices are numbered in the order they first report anything.
+	PenTool {
+		device: u32,
+		eraser: bool,
+	},
+	PenButton {
+		device: u32,
+		button: u32,
+		pressed: bool,
+	},
+	Key {
+		key: VirtualKeyCode,
+		pressed: bool,
+	},
+	Modifiers {
+		modifiers: ModifiersState,
+	},
+	/// The view was resized, in physical pixels
+	Resized {
+		width: u32,
+		height: u32,
+	},
+	/// The view moved to a display with a different scale factor
+	ScaleFactorChanged {
+		scale_factor: f64,
+	},
+}
+
+/// An input event with the time it happened, in seconds since the recording started
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+	pub time: f64,
+	pub event: InputEvent,
+}
+
+/// Writes input events to a file as they happen, one JSON object per line
+pub struct Recorder {
+	start: Instant,
+	writer: BufWriter<File>,
+}
+
+impl Recorder {
+	pub fn create(path: &Path) -> anyhow::Result<Self> {
+		log::info!("Recording input to '{}'", path.display());
+		Ok(Self {
+			start: Instant::now(),
+			writer: BufWriter::new(File::create(path)?),
+		})
+	}
+
+	pub fn record(&mut self, time: Instant, event: InputEvent) -> anyhow::Result<()> {
+		let recorded = RecordedEvent {
+			time: time.saturating_duration_since(self.start).as_secs_f64(),
+			event,
+		};
+		serde_json::to_writer(&mut self.writer, &recorded)?;
+		self.writer.write_all(b"\n")?;
+		// The event loop never returns, so don't count on the writer being dropped
+		self.writer.flush()?;
+		Ok(())
+	}
+}
+
+pub fn load_recording(path: &Path) -> anyhow::Result<Vec<RecordedEvent>> {
+	BufReader::new(File::open(path)?)
+		.lines()
+		.enumerate()
+		.filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+		.map(|(i, line)| {
+			serde_json::from_str(&line?)
+				.map_err(|e| anyhow::format_err!("Invalid event on line {} of '{}': {e}", i + 1, path.display()))
+		})
+		.collect()
+}
+
+/// Send recorded events to the event loop from a background thread at the times they were recorded, counted from
+/// `start`
+pub fn spawn_replay(events: Vec<RecordedEvent>, start: Instant, proxy: EventLoopProxy<RecordedEvent>) {
+	std::thread::spawn(move || {
+		for event in events {
+			let due = start + Duration::from_secs_f64(event.time.max(0.0));
+			std::thread::sleep(due.saturating_duration_since(Instant::now()));
+			if proxy.send_event(event).is_err() {
+				// The event loop is gone
+				return;
+			}
+		}
+		log::info!("Replay finished");
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{config::Config, editor::Editor};
+
+	/// Get the page positions and pressures of every sample on the canvas of an editor
+	fn samples(editor: &Editor) -> Vec<(f64, f64, f32)> {
+		let layers = editor.canvas.canvas.layers();
+		let samples = layers.iter().flat_map(|layer| {
+			layer.events().iter().map(|event| {
+				let pos = event.pos + layer.origin();
+				(pos.x, pos.y, event.pressure)
+			})
+		});
+		samples.collect()
+	}
+
+	#[test]
+	fn replaying_a_recording_draws_the_same_canvas() {
+		let button = MouseButton::Left;
+		let events = [
+			(
+				0,
+				InputEvent::Resized {
+					width: 1600,
+					height: 1200,
+				},
+			),
+			(0, InputEvent::ScaleFactorChanged { scale_factor: 2.0 }),
+			(5, InputEvent::MouseMoved { x: 10.0, y: 10.0 }),
+			(10, InputEvent::MouseButton { button, pressed: true }),
+			(20, InputEvent::MouseMoved { x: 50.0, y: 20.0 }),
+			(30, InputEvent::MouseMoved { x: 90.0, y: 70.0 }),
+			(40, InputEvent::MouseButton { button, pressed: false }),
+			(50, InputEvent::PenMoved { x: 300.0, y: 200.0 }),
+			(50, InputEvent::PenPressure { pressure: 0.25 }),
+			(60, InputEvent::PenDown),
+			(70, InputEvent::PenMoved { x: 350.0, y: 260.0 }),
+			(80, InputEvent::PenUp),
+		];
+
+		let path = std::env::temp_dir().join(format!("skyboard-record-test-{}.jsonl", std::process::id()));
+		let mut recorder = Recorder::create(&path).unwrap();
+		let mut live = Editor::new(Config::default(), 800, 600).unwrap();
+		for (millis, event) in events {
+			let time = recorder.start + Duration::from_millis(millis);
+			recorder.record(time, event).unwrap();
+			live.handle_input(event, time);
+		}
+		drop(recorder);
+
+		let recording = load_recording(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(recording.len(), events.len());
+
+		let mut replayed = Editor::new(Config::default(), 800, 600).unwrap();
+		let start = Instant::now();
+		for recorded in recording {
+			replayed.handle_input(recorded.event, start + Duration::from_secs_f64(recorded.time));
+		}
+
+		assert_eq!(live.canvas.canvas.stroke_count(), 2);
+		assert_eq!(samples(&replayed), samples(&live));
+		assert_eq!(replayed.canvas.scale_factor(), 2.0);
+		assert_eq!((replayed.canvas.get_width(), replayed.canvas.get_height()), (1600, 1200));
+	}
+}
@@ -0,0 +1,107 @@
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	path::PathBuf,
+	time::{Duration, Instant, SystemTime},
+};
+
+use wgpu::Device;
+
+/// Shaders built into the binary, by their file name in `src/shaders`
+const EMBEDDED: [(&str, &str); 3] = [
+	("blit.wgsl", include_str!("shaders/blit.wgsl")),
+	("cursor.wgsl", include_str!("shaders/cursor.wgsl")),
+	("tri_canvas.wgsl", include_str!("shaders/tri_canvas.wgsl")),
+];
+
+/// How often the hot reload directory is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Get the directory shaders are hot reloaded from, set with `SKYBOARD_SHADER_DIR`. This is meant for working on
+/// the shaders, usually with `SKYBOARD_SHADER_DIR=src/shaders`. Without it the embedded shaders are used.
+pub fn hot_reload_dir() -> Option<PathBuf> {
+	std::env::var_os("SKYBOARD_SHADER_DIR").map(PathBuf::from)
+}
+
+/// Get the WGSL source of a shader, preferring the hot reload directory if there is one
+pub fn source(name: &str) -> Cow<'static, str> {
+	if let Some(dir) = hot_reload_dir() {
+		match std::fs::read_to_string(dir.join(name)) {
+			Ok(source) => return source.into(),
+			Err(e) => log::warn!("Using the embedded {name}, failed to read it from '{}': {e}", dir.display()),
+		}
+	}
+
+	match EMBEDDED.iter().find(|(file, _)| *file == name) {
+		Some((_, source)) => Cow::Borrowed(source),
+		None => panic!("No shader named {name} is embedded"),
+	}
+}
+
+pub fn load(device: &Device, name: &str) -> wgpu::ShaderModule {
+	device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some(name),
+		source: wgpu::ShaderSource::Wgsl(source(name)),
+	})
+}
+
+/// Notices changes to the WGSL files in the hot reload directory by polling their modification times
+pub struct ShaderWatcher {
+	dir: PathBuf,
+	modified: HashMap<PathBuf, SystemTime>,
+	next_poll: Instant,
+}
+
+impl ShaderWatcher {
+	pub fn new(dir: PathBuf) -> Self {
+		log::info!("Hot reloading shaders from '{}'", dir.display());
+		let mut watcher = Self {
+			dir,
+			modified: HashMap::new(),
+			next_poll: Instant::now(),
+		};
+		watcher.modified = watcher.scan();
+		watcher
+	}
+
+	fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+		let entries = match std::fs::read_dir(&self.dir) {
+			Ok(entries) => entries,
+			Err(e) => {
+				log::warn!("Failed to read the shader directory '{}': {e}", self.dir.display());
+				return HashMap::new();
+			}
+		};
+		entries
+			.filter_map(|entry| {
+				let path = entry.ok()?.path();
+				if path.extension()? != "wgsl" {
+					return None;
+				}
+				let modified = path.metadata().and_then(|metadata| metadata.modified()).ok()?;
+				Some((path, modified))
+			})
+			.collect()
+	}
+
+	/// Get when `poll` should be called next
+	pub fn next_poll(&self) -> Instant {
+		self.next_poll
+	}
+
+	/// Check whether any shader changed since the last time, if it is time to look again
+	pub fn poll(&mut self) -> bool {
+		let now = Instant::now();
+		if now < self.next_poll {
+			return false;
+		}
+		self.next_poll = now + POLL_INTERVAL;
+
+		let modified = self.scan();
+		if modified == self.modified {
+			return false;
+		}
+		self.modified = modified;
+		true
+	}
+}
@@ -1,6 +1,5 @@
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) uv: vec2<f32>,
 };
 
 @vertex
@@ -8,20 +7,42 @@ fn vs_main(
     @builtin(vertex_index) index: u32,
 ) -> VertexOutput {
     var out: VertexOutput;
-	out.uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
-    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
     out.clip_position.y = -out.clip_position.y;
     return out;
 }
 
 // Fragment shader
+//
+// The canvas texture has a plain unorm format but holds sRGB-encoded colors, so its texels read back as the encoded
+// values. Which entry point is used depends on the target:
+// - `fs_encoded` for unorm targets, which are shown as they are, so the values are copied unchanged
+// - `fs_linear` for sRGB and float targets, which take linear values, so the values are decoded first
 
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0) @binding(1)
-var s_diffuse: sampler;
+var canvas: texture_2d<f32>;
+
+// Texels are loaded rather than sampled, so nothing is filtered and every value arrives exactly
+fn load(position: vec4<f32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(canvas));
+    let texel = clamp(vec2<i32>(position.xy), vec2<i32>(0, 0), size - vec2<i32>(1, 1));
+    return textureLoad(canvas, texel, 0);
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4, 2.4, 2.4));
+    return select(high, low, color <= vec3<f32>(0.04045, 0.04045, 0.04045));
+}
+
+@fragment
+fn fs_encoded(in: VertexOutput) -> @location(0) vec4<f32> {
+    return load(in.clip_position);
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.uv);
+fn fs_linear(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load(in.clip_position);
+    return vec4<f32>(srgb_to_linear(color.rgb), color.a);
 }
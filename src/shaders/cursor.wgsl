@@ -0,0 +1,33 @@
+struct Cursor {
+    color: vec4<f32>,
+    center: vec2<f32>,
+    radius: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> cursor: Cursor;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+	let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // A ring in the brush color with a white halo so it stays visible on any background
+    let offset = abs(distance(in.clip_position.xy, cursor.center) - cursor.radius);
+    let ring = clamp(1.25 - offset, 0.0, 1.0);
+    let halo = clamp(2.5 - offset, 0.0, 1.0);
+    return vec4<f32>(mix(vec3<f32>(1.0, 1.0, 1.0), cursor.color.rgb, ring), halo * cursor.color.a);
+}
@@ -1,7 +1,22 @@
 // Vertex shader
 
+struct View {
+    // The pan split into a chunk-aligned part and the rest, so vertices far from the page origin can be
+    // rebased against it without losing precision
+    pan_chunk: vec2<f32>,
+    pan_offset: vec2<f32>,
+    // Rotation and zoom from page to widget coordinates
+    linear: mat2x2<f32>,
+    size: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
 struct VertexInput {
-    @location(0) position: vec2<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) origin: vec2<f32>,
+    @location(2) color: vec4<f32>,
 };
 
 struct VertexOutput {
@@ -14,9 +29,9 @@ fn vs_main(
     model: VertexInput,
 ) -> VertexOutput {
     var out: VertexOutput;
-	
-    out.color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
-    out.clip_position = vec4<f32>((model.position.x / 1024.0 - 0.5) * 2.0, -(model.position.y / 768.0 - 0.5) * 2.0, 0.0, 1.0);
+    out.color = model.color;
+    let widget = view.linear * ((model.origin - view.pan_chunk) + model.local - view.pan_offset);
+    out.clip_position = vec4<f32>(widget.x / view.size.x * 2.0 - 1.0, 1.0 - widget.y / view.size.y * 2.0, 0.0, 1.0);
     return out;
 }
 
@@ -24,5 +39,5 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color);
+    return in.color;
 }
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use vello::peniko::Color;
+
+use crate::tool::ToolKind;
+
+/// What the eraser end or a barrel button of a pen does while it is in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TabletBinding {
+	/// Use a tool instead of the active one
+	Tool(ToolKind),
+	/// Draw with an RGB color instead of the brush color
+	Color([u8; 3]),
+}
+
+impl TabletBinding {
+	pub fn tool(self) -> Option<ToolKind> {
+		match self {
+			TabletBinding::Tool(kind) => Some(kind),
+			TabletBinding::Color(_) => None,
+		}
+	}
+
+	pub fn color(self) -> Option<Color> {
+		match self {
+			TabletBinding::Tool(_) => None,
+			TabletBinding::Color([r, g, b]) => Some(Color::rgb8(r, g, b)),
+		}
+	}
+}
+
+/// Bindings for pens, read from the `[tablet]` section of the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TabletConfig {
+	pub eraser: Option<TabletBinding>,
+	/// Barrel buttons by the number the platform reports for them
+	pub buttons: HashMap<String, TabletBinding>,
+	/// Bindings for single pens on top of the ones above, by the number of the pen. Pens are numbered from 0 in the
+	/// order they first report anything, since winit's `DeviceId` has no name that stays the same between runs.
+	pub devices: HashMap<String, TabletDeviceConfig>,
+}
+
+impl Default for TabletConfig {
+	fn default() -> Self {
+		Self {
+			eraser: Some(TabletBinding::Tool(ToolKind::Eraser)),
+			buttons: [("1".to_owned(), TabletBinding::Tool(ToolKind::Pan))].into_iter().collect(),
+			devices: HashMap::new(),
+		}
+	}
+}
+
+/// Bindings for a single pen, replacing the ones for all pens where given
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TabletDeviceConfig {
+	pub eraser: Option<TabletBinding>,
+	pub buttons: HashMap<String, TabletBinding>,
+}
+
+/// Bindings of the eraser end and barrel buttons, with the button numbers parsed
+struct Bindings {
+	eraser: Option<TabletBinding>,
+	buttons: HashMap<u32, TabletBinding>,
+}
+
+impl Bindings {
+	fn new(eraser: Option<TabletBinding>, buttons: HashMap<String, TabletBinding>) -> anyhow::Result<Self> {
+		let buttons = buttons
+			.into_iter()
+			.map(|(button, binding)| Ok((parse_number(&button, "tablet button")?, binding)))
+			.collect::<anyhow::Result<_>>()?;
+		Ok(Self { eraser, buttons })
+	}
+}
+
+fn parse_number(number: &str, what: &str) -> anyhow::Result<u32> {
+	number
+		.parse()
+		.map_err(|_| anyhow::format_err!("Invalid {what} '{number}' in config"))
+}
+
+/// The state of a single pen
+#[derive(Default)]
+struct TabletDevice {
+	/// Whether the eraser end is in use
+	eraser_end: bool,
+	/// Buttons currently held, in the order they were pressed
+	held: Vec<u32>,
+}
+
+/// Tracks which end of each pen is in use and which of its buttons are held, and looks up the bindings from the
+/// config for them. Devices are identified by the number the app assigns to each winit `DeviceId`.
+pub struct TabletDevices {
+	defaults: Bindings,
+	/// Bindings of single devices, which take precedence over `defaults`
+	overrides: HashMap<u32, Bindings>,
+	devices: HashMap<u32, TabletDevice>,
+	/// The device that most recently reported anything
+	current: Option<u32>,
+}
+
+impl TabletDevices {
+	pub fn new(config: TabletConfig) -> anyhow::Result<Self> {
+		let overrides = config
+			.devices
+			.into_iter()
+			.map(|(device, bindings)| {
+				let device = parse_number(&device, "tablet device")?;
+				Ok((device, Bindings::new(bindings.eraser, bindings.buttons)?))
+			})
+			.collect::<anyhow::Result<_>>()?;
+
+		Ok(Self {
+			defaults: Bindings::new(config.eraser, config.buttons)?,
+			overrides,
+			devices: HashMap::new(),
+			current: None,
+		})
+	}
+
+	fn device(&mut self, device: u32) -> &mut TabletDevice {
+		self.current = Some(device);
+		self.devices.entry(device).or_default()
+	}
+
+	pub fn handle_tool(&mut self, device: u32, eraser_end: bool) {
+		self.device(device).eraser_end = eraser_end;
+	}
+
+	pub fn handle_button(&mut self, device: u32, button: u32, pressed: bool) {
+		let device = self.device(device);
+		device.held.retain(|&held| held != button);
+		if pressed {
+			device.held.push(button);
+		}
+	}
+
+	/// Get the binding in effect for the pen in use, if any. The eraser end takes precedence over held buttons, and
+	/// later presses over earlier ones.
+	pub fn binding(&self) -> Option<TabletBinding> {
+		let current = self.current?;
+		let device = self.devices.get(&current)?;
+		let overrides = self.overrides.get(&current);
+		let button = device.held.iter().rev().find_map(|button| {
+			overrides
+				.and_then(|bindings| bindings.buttons.get(button))
+				.or_else(|| self.defaults.buttons.get(button))
+				.copied()
+		});
+		if device.eraser_end {
+			overrides
+				.and_then(|bindings| bindings.eraser)
+				.or(self.defaults.eraser)
+				.or(button)
+		} else {
+			button
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RED: TabletBinding = TabletBinding::Color([255, 0, 0]);
+
+	fn devices(config: &str) -> TabletDevices {
+		TabletDevices::new(toml::from_str(config).unwrap()).unwrap()
+	}
+
+	#[test]
+	fn eraser_end_takes_precedence_over_buttons() {
+		let mut tablets = devices("");
+		assert_eq!(tablets.binding(), None);
+
+		tablets.handle_button(0, 1, true);
+		assert_eq!(tablets.binding(), Some(TabletBinding::Tool(ToolKind::Pan)));
+		tablets.handle_tool(0, true);
+		assert_eq!(tablets.binding(), Some(TabletBinding::Tool(ToolKind::Eraser)));
+		tablets.handle_tool(0, false);
+		tablets.handle_button(0, 1, false);
+		assert_eq!(tablets.binding(), None);
+	}
+
+	#[test]
+	fn later_presses_win_until_released() {
+		let mut tablets = devices(
+			r#"
+				[buttons]
+				2 = { color = [255, 0, 0] }
+			"#,
+		);
+		tablets.handle_button(0, 1, true);
+		tablets.handle_button(0, 2, true);
+		assert_eq!(tablets.binding(), Some(RED));
+		// Buttons without a binding don't hide the ones held before them
+		tablets.handle_button(0, 3, true);
+		assert_eq!(tablets.binding(), Some(RED));
+		tablets.handle_button(0, 2, false);
+		assert_eq!(tablets.binding(), Some(TabletBinding::Tool(ToolKind::Pan)));
+	}
+
+	#[test]
+	fn devices_override_the_bindings_for_all_pens() {
+		let mut tablets = devices(
+			r#"
+				[devices.1]
+				eraser = { tool = "lasso" }
+				buttons = { 1 = { color = [255, 0, 0] } }
+			"#,
+		);
+		tablets.handle_button(0, 1, true);
+		assert_eq!(tablets.binding(), Some(TabletBinding::Tool(ToolKind::Pan)));
+
+		// The binding follows the pen that reported last, with its own state
+		tablets.handle_button(1, 1, true);
+		assert_eq!(tablets.binding(), Some(RED));
+		tablets.handle_tool(1, true);
+		assert_eq!(tablets.binding(), Some(TabletBinding::Tool(ToolKind::Lasso)));
+		tablets.handle_tool(0, true);
+		assert_eq!(tablets.binding(), Some(TabletBinding::Tool(ToolKind::Eraser)));
+	}
+
+	#[test]
+	fn invalid_numbers_are_rejected() {
+		let config = |config: &str| TabletDevices::new(toml::from_str(config).unwrap());
+		assert!(config("buttons = { first = { tool = \"pan\" } }").is_err());
+		assert!(config("[devices.pen]").is_err());
+		assert!(config("[devices.0]\nbuttons = { -1 = { tool = \"pan\" } }").is_err());
+	}
+}
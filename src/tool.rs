@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use linalg::prelude::*;
+use serde::Deserialize;
+use winit::event::ModifiersState;
+
+use crate::{canvas::CanvasWidget, cursor::BrushCursor, pen::STROKE_WIDTH};
+
+mod eraser;
+mod lasso;
+mod pan;
+mod pen;
+mod shape;
+
+pub use self::{eraser::EraserTool, lasso::LassoTool, pan::PanTool, pen::PenTool, shape::*};
+
+/// A pointer sample in widget coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointerEvent {
+	pub pos: Point2,
+	pub pressure: f32,
+	/// Modifier keys held at the time, e.g. Shift to constrain shapes
+	pub modifiers: ModifiersState,
+}
+
+impl PointerEvent {
+	/// Map the position to the page
+	pub fn page_pos(&self, canvas: &CanvasWidget) -> Point2 {
+		canvas.transform() * self.pos
+	}
+
+	/// Map the position to the page and snap it to the grid if that is on, for tools that place exact points
+	pub fn snapped_page_pos(&self, canvas: &CanvasWidget) -> Point2 {
+		canvas.snap(self.page_pos(canvas))
+	}
+}
+
+/// Where pointer input comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerSource {
+	Mouse,
+	Pen,
+	Touch,
+}
+
+/// Something that turns pointer input into edits of the canvas or its view
+pub trait Tool {
+	fn pointer_down(&mut self, canvas: &mut CanvasWidget, event: PointerEvent);
+
+	fn pointer_move(&mut self, canvas: &mut CanvasWidget, event: PointerEvent);
+
+	fn pointer_up(&mut self, canvas: &mut CanvasWidget, event: PointerEvent);
+
+	/// The pointer moved without being pressed
+	fn hover(&mut self, _canvas: &mut CanvasWidget, _event: PointerEvent) {}
+
+	/// Abandon the press in progress, e.g. because the tool was switched or a touch turned into a gesture
+	fn cancel(&mut self, canvas: &mut CanvasWidget);
+
+	/// Get the outline to draw at a pointer position in widget coordinates, if this tool has one
+	fn cursor(&self, _canvas: &CanvasWidget, _pos: Point2) -> Option<BrushCursor> {
+		None
+	}
+}
+
+/// Get a cursor outlining the brush at a pointer position
+pub fn brush_cursor(canvas: &CanvasWidget, pos: Point2) -> BrushCursor {
+	BrushCursor {
+		center: [pos.x, pos.y],
+		radius: STROKE_WIDTH as f64 * 0.5 * canvas.pixel_scale(),
+		color: canvas.canvas.color,
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolKind {
+	Pen,
+	Eraser,
+	Lasso,
+	Pan,
+	Line,
+	Rectangle,
+	Ellipse,
+}
+
+/// Holds the registered tools and routes pointer input to the one in use
+pub struct ToolManager {
+	tools: HashMap<ToolKind, Box<dyn Tool>>,
+	active: ToolKind,
+	/// A tool temporarily used instead of the active one, e.g. while Space is held
+	temporary: Option<ToolKind>,
+	/// The tool and source of the press in progress. Input keeps going to this tool until the press ends, even if
+	/// the active tool changes in the meantime.
+	pressed: Option<(ToolKind, PointerSource)>,
+}
+
+impl ToolManager {
+	pub fn new() -> Self {
+		let mut manager = Self {
+			tools: HashMap::new(),
+			active: ToolKind::Pen,
+			temporary: None,
+			pressed: None,
+		};
+		manager.register(ToolKind::Pen, Box::new(PenTool::new()));
+		manager.register(ToolKind::Eraser, Box::new(EraserTool::new()));
+		manager.register(ToolKind::Lasso, Box::new(LassoTool::new()));
+		manager.register(ToolKind::Pan, Box::new(PanTool::new()));
+		manager.register(ToolKind::Line, Box::new(ShapeTool::new(ShapeKind::Line)));
+		manager.register(ToolKind::Rectangle, Box::new(ShapeTool::new(ShapeKind::Rectangle)));
+		manager.register(ToolKind::Ellipse, Box::new(ShapeTool::new(ShapeKind::Ellipse)));
+		manager
+	}
+
+	pub fn register(&mut self, kind: ToolKind, tool: Box<dyn Tool>) {
+		self.tools.insert(kind, tool);
+	}
+
+	/// Get the tool that a new press would use
+	pub fn current(&self) -> ToolKind {
+		self.temporary.unwrap_or(self.active)
+	}
+
+	pub fn set_active(&mut self, kind: ToolKind) {
+		self.active = kind;
+	}
+
+	pub fn set_temporary(&mut self, kind: Option<ToolKind>) {
+		self.temporary = kind;
+	}
+
+	pub fn is_pressed(&self, source: PointerSource) -> bool {
+		matches!(self.pressed, Some((_, pressed)) if pressed == source)
+	}
+
+	/// Start a press with the current tool. Returns false if another press is already in progress.
+	pub fn pointer_down(&mut self, source: PointerSource, canvas: &mut CanvasWidget, event: PointerEvent) -> bool {
+		self.pointer_down_with(self.current(), source, canvas, event)
+	}
+
+	/// Start a press with a specific tool. Returns false if another press is already in progress.
+	pub fn pointer_down_with(
+		&mut self,
+		kind: ToolKind,
+		source: PointerSource,
+		canvas: &mut CanvasWidget,
+		event: PointerEvent,
+	) -> bool {
+		if self.pressed.is_some() {
+			return false;
+		}
+		match self.tools.get_mut(&kind) {
+			Some(tool) => {
+				self.pressed = Some((kind, source));
+				tool.pointer_down(canvas, event);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Route pointer motion to the pressed tool, or as a hover to the current tool if nothing is pressed
+	pub fn pointer_move(&mut self, source: PointerSource, canvas: &mut CanvasWidget, event: PointerEvent) {
+		match self.pressed {
+			Some((kind, pressed)) if pressed == source => {
+				if let Some(tool) = self.tools.get_mut(&kind) {
+					tool.pointer_move(canvas, event);
+				}
+			}
+			Some(_) => {}
+			None => {
+				let current = self.current();
+				if let Some(tool) = self.tools.get_mut(&current) {
+					tool.hover(canvas, event);
+				}
+			}
+		}
+	}
+
+	/// End the press from a source. Returns false if that source wasn't pressing.
+	pub fn pointer_up(&mut self, source: PointerSource, canvas: &mut CanvasWidget, event: PointerEvent) -> bool {
+		match self.pressed {
+			Some((kind, pressed)) if pressed == source => {
+				self.pressed = None;
+				if let Some(tool) = self.tools.get_mut(&kind) {
+					tool.pointer_up(canvas, event);
+				}
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Get the cursor of the pressed tool, or of the current tool if nothing is pressed
+	pub fn cursor(&self, canvas: &CanvasWidget, pos: Point2) -> Option<BrushCursor> {
+		let kind = self.pressed.map_or(self.current(), |(kind, _)| kind);
+		self.tools.get(&kind)?.cursor(canvas, pos)
+	}
+
+	/// Abandon the press in progress, if any
+	pub fn cancel(&mut self, canvas: &mut CanvasWidget) {
+		if let Some((kind, _)) = self.pressed.take() {
+			if let Some(tool) = self.tools.get_mut(&kind) {
+				tool.cancel(canvas);
+			}
+		}
+	}
+}
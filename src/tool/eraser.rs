@@ -0,0 +1,50 @@
+use linalg::prelude::*;
+use vello::peniko::Color;
+
+use super::{PointerEvent, Tool};
+use crate::{canvas::CanvasWidget, cursor::BrushCursor};
+
+/// Radius of the eraser in logical pixels
+const ERASER_RADIUS: f64 = 8.0;
+
+/// Removes whole strokes that the pointer passes over
+pub struct EraserTool;
+
+impl EraserTool {
+	pub fn new() -> Self {
+		Self
+	}
+
+	fn erase(canvas: &mut CanvasWidget, event: PointerEvent) {
+		let point = event.page_pos(canvas);
+		let radius = ERASER_RADIUS / canvas.zoom;
+		canvas.canvas.erase_at(point, radius);
+	}
+}
+
+impl Tool for EraserTool {
+	fn pointer_down(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		Self::erase(canvas, event);
+	}
+
+	fn pointer_move(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		Self::erase(canvas, event);
+	}
+
+	fn pointer_up(&mut self, canvas: &mut CanvasWidget, _event: PointerEvent) {
+		canvas.canvas.commit_erase();
+	}
+
+	fn cancel(&mut self, canvas: &mut CanvasWidget) {
+		// Strokes already erased stay erased, so keep them undoable
+		canvas.canvas.commit_erase();
+	}
+
+	fn cursor(&self, canvas: &CanvasWidget, pos: Point2) -> Option<BrushCursor> {
+		Some(BrushCursor {
+			center: [pos.x, pos.y],
+			radius: ERASER_RADIUS * canvas.scale_factor(),
+			color: Color::rgb8(0x80, 0x80, 0x80),
+		})
+	}
+}
@@ -0,0 +1,90 @@
+use linalg::prelude::*;
+
+use super::{PointerEvent, Tool};
+use crate::canvas::CanvasWidget;
+
+enum LassoState {
+	Idle,
+	/// Drawing the outline of a new selection. The outline lives in `CanvasWidget::guide`.
+	Selecting,
+	/// Dragging the current selection
+	Moving {
+		/// Page position the drag started at
+		start: Point2,
+		/// Position of a sample of the selection when the drag started, which is what snaps to the grid
+		anchor: Point2,
+		/// How far the selection was moved so far
+		moved: Vec2,
+	},
+}
+
+/// Selects strokes by drawing around them, and moves the selection by dragging inside it
+pub struct LassoTool {
+	state: LassoState,
+}
+
+impl LassoTool {
+	pub fn new() -> Self {
+		Self { state: LassoState::Idle }
+	}
+}
+
+impl Tool for LassoTool {
+	fn pointer_down(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		let point = event.page_pos(canvas);
+		match canvas.canvas.selection_anchor() {
+			Some(anchor) if canvas.canvas.selection_contains(point) => {
+				self.state = LassoState::Moving {
+					start: point,
+					anchor,
+					moved: Vec2::zero(),
+				};
+			}
+			_ => {
+				canvas.canvas.clear_selection();
+				canvas.guide = vec![point];
+				self.state = LassoState::Selecting;
+			}
+		}
+	}
+
+	fn pointer_move(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		let point = event.page_pos(canvas);
+		match self.state {
+			LassoState::Idle => {}
+			LassoState::Selecting => canvas.guide.push(point),
+			LassoState::Moving {
+				start,
+				anchor,
+				ref mut moved,
+			} => {
+				let target = canvas.snap(anchor + (point - start));
+				let delta = target - anchor - *moved;
+				if delta != Vec2::zero() {
+					canvas.canvas.move_selection(delta);
+					*moved += delta;
+				}
+			}
+		}
+	}
+
+	fn pointer_up(&mut self, canvas: &mut CanvasWidget, _event: PointerEvent) {
+		match std::mem::replace(&mut self.state, LassoState::Idle) {
+			LassoState::Idle => {}
+			LassoState::Selecting => {
+				let outline = std::mem::take(&mut canvas.guide);
+				if outline.len() >= 3 {
+					canvas.canvas.select_in_polygon(&outline);
+				}
+			}
+			LassoState::Moving { .. } => canvas.canvas.commit_move(),
+		}
+	}
+
+	fn cancel(&mut self, canvas: &mut CanvasWidget) {
+		if let LassoState::Moving { .. } = std::mem::replace(&mut self.state, LassoState::Idle) {
+			canvas.canvas.commit_move();
+		}
+		canvas.guide.clear();
+	}
+}
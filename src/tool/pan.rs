@@ -0,0 +1,36 @@
+use linalg::prelude::*;
+
+use super::{PointerEvent, Tool};
+use crate::canvas::CanvasWidget;
+
+/// Drags the view around
+pub struct PanTool {
+	last_pos: Option<Point2>,
+}
+
+impl PanTool {
+	pub fn new() -> Self {
+		Self { last_pos: None }
+	}
+}
+
+impl Tool for PanTool {
+	fn pointer_down(&mut self, _canvas: &mut CanvasWidget, event: PointerEvent) {
+		self.last_pos = Some(event.pos);
+	}
+
+	fn pointer_move(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		if let Some(last_pos) = self.last_pos.replace(event.pos) {
+			// Dragging left = panning right
+			canvas.pan -= canvas.transform() * (event.pos - last_pos);
+		}
+	}
+
+	fn pointer_up(&mut self, _canvas: &mut CanvasWidget, _event: PointerEvent) {
+		self.last_pos = None;
+	}
+
+	fn cancel(&mut self, _canvas: &mut CanvasWidget) {
+		self.last_pos = None;
+	}
+}
@@ -0,0 +1,36 @@
+use linalg::prelude::*;
+
+use super::{brush_cursor, PointerEvent, Tool};
+use crate::{canvas::CanvasWidget, cursor::BrushCursor};
+
+/// Draws freehand strokes
+pub struct PenTool;
+
+impl PenTool {
+	pub fn new() -> Self {
+		Self
+	}
+}
+
+impl Tool for PenTool {
+	fn pointer_down(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		canvas.canvas.start_stroke();
+		canvas.canvas.move_stroke(event.page_pos(canvas), event.pressure);
+	}
+
+	fn pointer_move(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		canvas.canvas.move_stroke(event.page_pos(canvas), event.pressure);
+	}
+
+	fn pointer_up(&mut self, canvas: &mut CanvasWidget, _event: PointerEvent) {
+		canvas.canvas.end_stroke();
+	}
+
+	fn cancel(&mut self, canvas: &mut CanvasWidget) {
+		canvas.canvas.cancel_stroke();
+	}
+
+	fn cursor(&self, canvas: &CanvasWidget, pos: Point2) -> Option<BrushCursor> {
+		Some(brush_cursor(canvas, pos))
+	}
+}
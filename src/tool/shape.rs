@@ -0,0 +1,102 @@
+use linalg::prelude::*;
+
+use super::{brush_cursor, PointerEvent, Tool};
+use crate::{canvas::CanvasWidget, cursor::BrushCursor};
+
+/// Number of segments used to approximate an ellipse
+const ELLIPSE_SEGMENTS: usize = 64;
+/// Angle in radians between the directions a line is constrained to while Shift is held
+pub(crate) const ANGLE_STEP: f64 = 15.0 * std::f64::consts::PI / 180.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeKind {
+	Line,
+	Rectangle,
+	Ellipse,
+}
+
+impl ShapeKind {
+	/// Get the outline of the shape spanned between two page points
+	pub fn outline(self, start: Point2, end: Point2) -> Vec<Point2> {
+		match self {
+			ShapeKind::Line => vec![start, end],
+			ShapeKind::Rectangle => vec![start, Point2::new(end.x, start.y), end, Point2::new(start.x, end.y), start],
+			ShapeKind::Ellipse => {
+				let center = start + (end - start) * 0.5;
+				let radii = (end - start) * 0.5;
+				(0..=ELLIPSE_SEGMENTS)
+					.map(|i| {
+						let angle = i as f64 / ELLIPSE_SEGMENTS as f64 * std::f64::consts::TAU;
+						center + Vec2::new(radii.x * angle.cos(), radii.y * angle.sin())
+					})
+					.collect()
+			}
+		}
+	}
+}
+
+/// Turn `end` around `start` to the nearest direction that is a multiple of `ANGLE_STEP`, keeping how far it reaches
+/// along that direction
+fn snap_angle(start: Point2, end: Point2) -> Point2 {
+	let offset = end - start;
+	let angle = (offset.y.atan2(offset.x) / ANGLE_STEP).round() * ANGLE_STEP;
+	let direction = Vec2::new(angle.cos(), angle.sin());
+	start + direction * offset.dot(&direction)
+}
+
+/// Drags out a straight-edged or elliptical stroke between the press and release positions
+pub struct ShapeTool {
+	kind: ShapeKind,
+	start: Option<Point2>,
+}
+
+impl ShapeTool {
+	pub fn new(kind: ShapeKind) -> Self {
+		Self { kind, start: None }
+	}
+
+	/// Get where the shape ends on the page. Lines held with Shift keep to multiples of `ANGLE_STEP`, which takes
+	/// precedence over snapping to the grid.
+	fn end(&self, canvas: &CanvasWidget, start: Point2, event: PointerEvent) -> Point2 {
+		if self.kind == ShapeKind::Line && event.modifiers.shift() {
+			snap_angle(start, event.page_pos(canvas))
+		} else {
+			event.snapped_page_pos(canvas)
+		}
+	}
+
+	fn reshape(&self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		if let Some(start) = self.start {
+			let end = self.end(canvas, start, event);
+			let points: Vec<_> = self.kind.outline(start, end).into_iter().map(|point| (point, 1.0)).collect();
+			canvas.canvas.replace_stroke(&points);
+		}
+	}
+}
+
+impl Tool for ShapeTool {
+	fn pointer_down(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		self.start = Some(event.snapped_page_pos(canvas));
+		canvas.canvas.start_stroke();
+		self.reshape(canvas, event);
+	}
+
+	fn pointer_move(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		self.reshape(canvas, event);
+	}
+
+	fn pointer_up(&mut self, canvas: &mut CanvasWidget, event: PointerEvent) {
+		self.reshape(canvas, event);
+		self.start = None;
+		canvas.canvas.end_stroke();
+	}
+
+	fn cancel(&mut self, canvas: &mut CanvasWidget) {
+		self.start = None;
+		canvas.canvas.cancel_stroke();
+	}
+
+	fn cursor(&self, canvas: &CanvasWidget, pos: Point2) -> Option<BrushCursor> {
+		Some(brush_cursor(canvas, pos))
+	}
+}
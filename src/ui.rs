@@ -1,93 +1,357 @@
+use std::{
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
 use wgpu::{Instance, RenderPipeline, Texture, TextureFormat, TextureView};
 use winit::{
-	dpi::LogicalSize,
+	dpi::{LogicalSize, PhysicalSize},
 	event_loop::EventLoop,
 	window::{Window, WindowBuilder},
 };
 
-use crate::{blit::BlitPipeline, canvas::CanvasWidget, Graphics};
+use crate::{
+	blit::BlitPipeline,
+	canvas::{self, BackendKind, CanvasBackend, CanvasWidget, StrokeSync},
+	cursor::{BrushCursor, CursorPipeline},
+	gpu::{self, GpuConfig},
+	shader::{self, ShaderWatcher},
+	Graphics,
+};
+
+/// How many times the GPU state may be rebuilt within `RECOVERY_WINDOW` before the app gives up
+const MAX_RECOVERIES: usize = 3;
+const RECOVERY_WINDOW: Duration = Duration::from_secs(10);
 
+/// The windowed frontend: shows the canvas of an `Editor` on a window surface
 pub struct Ui {
-	pub graphics: Graphics,
 	pub window: Window,
-	surface: wgpu::Surface,
-	blitter: BlitPipeline,
-	pub canvas: CanvasWidget,
+	presenter: Presenter,
+	/// What the presenter was made with, to make it again after losing the GPU
+	backend: BackendKind,
+	gpu_config: GpuConfig,
+	recoveries: Recoveries,
+	/// Whether the canvas has to be re-rendered before the next present
+	canvas_dirty: bool,
+	/// The brush outline drawn over the canvas
+	pub cursor: Option<BrushCursor>,
+}
+
+/// How the canvas gets onto the window
+enum Presenter {
+	Gpu(GpuPresenter),
+	#[cfg(feature = "backend-raster")]
+	Software(SoftwarePresenter),
+	/// The GPU state was dropped and is being rebuilt
+	Lost,
+}
+
+/// The device or the surface is gone, so everything made with them has to be made again
+#[derive(Debug)]
+struct GpuLost(String);
+
+impl std::fmt::Display for GpuLost {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Lost the GPU: {}", self.0)
+	}
+}
+
+impl std::error::Error for GpuLost {}
+
+/// Whether an error the device reported outside of an error scope means the device is gone. wgpu reports that as a
+/// validation error of whatever was called on the device next. Other errors leave the device usable.
+fn is_device_loss(error: &wgpu::Error) -> bool {
+	matches!(error, wgpu::Error::Validation { description, .. } if description.contains("device is lost"))
+}
+
+/// When the GPU state was rebuilt recently, so a GPU that keeps failing ends the app instead of rebuilding forever
+#[derive(Debug, Default)]
+struct Recoveries {
+	times: Vec<Instant>,
+}
+
+impl Recoveries {
+	/// Count a rebuild at `now`, failing if there were `MAX_RECOVERIES` within `RECOVERY_WINDOW` before it
+	fn start(&mut self, now: Instant) -> anyhow::Result<()> {
+		self.times.retain(|&time| now.duration_since(time) < RECOVERY_WINDOW);
+		if self.times.len() >= MAX_RECOVERIES {
+			return Err(anyhow::format_err!(
+				"Lost the GPU {MAX_RECOVERIES} times within {RECOVERY_WINDOW:?}, giving up"
+			));
+		}
+		self.times.push(now);
+		Ok(())
+	}
 }
 
 impl Ui {
-	pub fn new(event_loop: &mut EventLoop<()>) -> anyhow::Result<Self> {
-		let instance = Instance::new(wgpu::Backends::VULKAN);
-		let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-			power_preference: wgpu::PowerPreference::HighPerformance,
-			..Default::default()
-		}))
-		.ok_or(anyhow::format_err!("Failed to find a graphics adapter"))?;
-		let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))?;
-		let graphics = Graphics { instance, device, queue };
-		let blitter = BlitPipeline::new(&graphics, TextureFormat::Bgra8UnormSrgb);
-
-		let (width, height) = (1024, 768);
+	pub fn new<T>(event_loop: &mut EventLoop<T>, backend: BackendKind, gpu_config: &GpuConfig) -> anyhow::Result<Self> {
 		let window = WindowBuilder::new()
-			.with_inner_size(LogicalSize::new(width, height))
+			.with_inner_size(LogicalSize::new(1024, 768))
 			.with_visible(true)
 			.build(event_loop)?;
+		log::info!(
+			"Window is {:?} pixels at scale factor {}",
+			window.inner_size(),
+			window.scale_factor()
+		);
+		let presenter = Self::create_presenter(&window, backend, gpu_config)?;
 
-		let surface = unsafe { graphics.instance.create_surface(&window) };
-		let config = wgpu::SurfaceConfiguration {
-			usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::RENDER_ATTACHMENT,
-			format: wgpu::TextureFormat::Bgra8UnormSrgb,
-			width,
-			height,
-			present_mode: wgpu::PresentMode::AutoVsync,
-			alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+		Ok(Self {
+			window,
+			presenter,
+			backend,
+			gpu_config: gpu_config.clone(),
+			recoveries: Recoveries::default(),
+			canvas_dirty: true,
+			cursor: None,
+		})
+	}
+
+	/// Make a presenter for the window, falling back to software rendering if the GPU isn't usable
+	fn create_presenter(window: &Window, backend: BackendKind, gpu_config: &GpuConfig) -> anyhow::Result<Presenter> {
+		// The canvas is rendered at the physical resolution of the window, not the logical one it was created with
+		let PhysicalSize { width, height } = window.inner_size();
+		Ok(match GpuPresenter::new(window, backend, gpu_config, width, height) {
+			Ok(presenter) => Presenter::Gpu(presenter),
+			#[cfg(feature = "backend-raster")]
+			Err(e) => {
+				log::warn!("Can't render with the GPU, falling back to software rendering: {e:#}");
+				Presenter::Software(SoftwarePresenter::new(window, width, height)?)
+			}
+			#[cfg(not(feature = "backend-raster"))]
+			Err(e) => return Err(e.context("Can't render with the GPU, and the backend-raster feature isn't compiled in")),
+		})
+	}
+
+	/// Build the GPU state again after losing the device or the surface. The strokes live in the editor's canvas,
+	/// so nothing is lost: the new backend renders all of them on the next redraw.
+	fn recover(&mut self) -> anyhow::Result<()> {
+		self.recoveries.start(Instant::now())?;
+
+		// The old surface has to be gone before another one can be made for the window
+		self.presenter = Presenter::Lost;
+		self.presenter = Self::create_presenter(&self.window, self.backend, &self.gpu_config)?;
+		self.invalidate_canvas();
+		self.window.request_redraw();
+		Ok(())
+	}
+
+	/// Resize the canvas to a new physical size of the window, also when it moved to a display with another scale factor
+	pub fn handle_window_resize(&mut self, size: PhysicalSize<u32>) {
+		log::debug!("Resizing to {:?}", size);
+		match &mut self.presenter {
+			Presenter::Gpu(presenter) => presenter.resize(size.width, size.height),
+			#[cfg(feature = "backend-raster")]
+			Presenter::Software(presenter) => presenter.resize(size.width, size.height),
+			Presenter::Lost => {}
+		}
+
+		self.invalidate_canvas();
+	}
+
+	/// Get the number of physical pixels per logical pixel of the display the window is on
+	pub fn scale_factor(&self) -> f64 {
+		self.window.scale_factor()
+	}
+
+	/// Get the size of the rendered canvas in physical pixels
+	pub fn size(&self) -> (u32, u32) {
+		match &self.presenter {
+			Presenter::Gpu(presenter) => {
+				let output = presenter.backend.output();
+				(output.get_width(), output.get_height())
+			}
+			#[cfg(feature = "backend-raster")]
+			Presenter::Software(presenter) => (presenter.canvas.get_width(), presenter.canvas.get_height()),
+			Presenter::Lost => self.window.inner_size().into(),
+		}
+	}
+
+	/// Re-render the canvas on the next redraw
+	pub fn invalidate_canvas(&mut self) {
+		self.canvas_dirty = true;
+		self.window.request_redraw();
+	}
+
+	/// Get when `poll_shaders` should run next, if shaders are hot reloaded
+	pub fn next_shader_poll(&self) -> Option<Instant> {
+		match &self.presenter {
+			Presenter::Gpu(presenter) => presenter.shader_watcher.as_ref().map(ShaderWatcher::next_poll),
+			#[cfg(feature = "backend-raster")]
+			Presenter::Software(_) => None,
+			Presenter::Lost => None,
+		}
+	}
+
+	/// Rebuild the pipelines if their shaders changed on disk
+	pub fn poll_shaders(&mut self) {
+		if let Presenter::Gpu(presenter) = &mut self.presenter {
+			if presenter.shader_watcher.as_mut().map_or(false, ShaderWatcher::poll) {
+				presenter.reload_shaders();
+				// The backend may draw the canvas with a new pipeline
+				self.invalidate_canvas();
+			}
+		}
+	}
+
+	/// Render the canvas if it changed and present it with the cursor on top. If the GPU was lost, its state is
+	/// rebuilt and the canvas is rendered again on the next redraw.
+	pub fn render(&mut self, canvas: &CanvasWidget) -> anyhow::Result<()> {
+		let dirty = std::mem::replace(&mut self.canvas_dirty, false);
+		self.window.set_cursor_visible(self.cursor.is_none());
+		let result = match &mut self.presenter {
+			Presenter::Gpu(presenter) => presenter.frame(&self.window, dirty.then_some(canvas), self.cursor.as_ref()),
+			#[cfg(feature = "backend-raster")]
+			Presenter::Software(presenter) => {
+				if dirty {
+					presenter.strokes.apply(&canvas.canvas, &mut presenter.canvas);
+					presenter.canvas.update(canvas);
+				}
+				presenter.present(self.cursor.as_ref())
+			}
+			Presenter::Lost => Ok(()),
 		};
-		surface.configure(&graphics.device, &config);
 
-		let canvas = CanvasWidget::new(&graphics, width, height)?;
+		match result {
+			Err(e) if e.is::<GpuLost>() => {
+				log::error!("{e:#}, rebuilding the GPU state");
+				self.recover()
+			}
+			result => result,
+		}
+	}
+}
 
-		Ok(Self {
+/// Renders the canvas with a `CanvasBackend` and blits it to a wgpu surface
+struct GpuPresenter {
+	graphics: Graphics,
+	surface: wgpu::Surface,
+	blitter: BlitPipeline,
+	cursor_pipeline: CursorPipeline,
+	backend: Box<dyn CanvasBackend>,
+	/// Passes the changes to the strokes on to the backend
+	strokes: StrokeSync,
+	format: TextureFormat,
+	alpha_mode: wgpu::CompositeAlphaMode,
+	present_mode: wgpu::PresentMode,
+	shader_watcher: Option<ShaderWatcher>,
+	/// The error the device reported outside of an error scope when it was lost
+	device_error: Arc<Mutex<Option<String>>>,
+}
+
+impl GpuPresenter {
+	fn new(window: &Window, backend: BackendKind, gpu_config: &GpuConfig, width: u32, height: u32) -> anyhow::Result<Self> {
+		let (graphics, surface) = Graphics::new(gpu_config, Some(window))?;
+		// Errors are logged instead of panicking, and losing the device is recorded so it can be replaced
+		let device_error = Arc::new(Mutex::new(None));
+		let handler_error = device_error.clone();
+		graphics.device.on_uncaptured_error(move |e| {
+			log::error!("Graphics device error: {e}");
+			if is_device_loss(&e) {
+				*handler_error.lock().unwrap() = Some(e.to_string());
+			}
+		});
+		let surface = surface.expect("Graphics::new creates a surface for the window");
+		let format = gpu::choose_surface_format(&surface.get_supported_formats(&graphics.adapter))?;
+		let alpha_mode = gpu::choose_alpha_mode(&surface.get_supported_alpha_modes(&graphics.adapter));
+		let supported_modes = surface.get_supported_present_modes(&graphics.adapter);
+		let present_mode = gpu::choose_present_mode(gpu_config.present_mode, &supported_modes);
+		let blitter = BlitPipeline::new(&graphics, format);
+		let cursor_pipeline = CursorPipeline::new(&graphics, format);
+		let backend = canvas::create_backend(backend, &graphics, width, height)?;
+
+		let mut presenter = Self {
 			graphics,
-			window,
 			surface,
 			blitter,
-			canvas,
-		})
+			cursor_pipeline,
+			backend,
+			strokes: StrokeSync::default(),
+			format,
+			alpha_mode,
+			present_mode,
+			shader_watcher: shader::hot_reload_dir().map(ShaderWatcher::new),
+			device_error,
+		};
+		presenter.configure_surface(width, height);
+		Ok(presenter)
 	}
 
-	pub fn handle_window_resize(&mut self) {
-		let size = self.window.inner_size();
-		dbg!("Resizing to {:?}", size);
+	fn configure_surface(&mut self, width: u32, height: u32) {
 		let config = wgpu::SurfaceConfiguration {
 			usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::RENDER_ATTACHMENT,
-			format: wgpu::TextureFormat::Bgra8UnormSrgb,
-			width: size.width,
-			height: size.height,
-			present_mode: wgpu::PresentMode::AutoVsync,
-			alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+			format: self.format,
+			width,
+			height,
+			present_mode: self.present_mode,
+			alpha_mode: self.alpha_mode,
 		};
 		self.surface.configure(&self.graphics.device, &config);
-		self.canvas.resize(&self.graphics, size.width, size.height);
+	}
 
-		self.window.request_redraw();
+	/// Rebuild the pipelines from the current shader sources, keeping the old ones if the new shaders don't compile
+	fn reload_shaders(&mut self) {
+		log::info!("Shaders changed, rebuilding pipelines");
+		self.graphics.device.push_error_scope(wgpu::ErrorFilter::Validation);
+		let blitter = BlitPipeline::new(&self.graphics, self.format);
+		let cursor_pipeline = CursorPipeline::new(&self.graphics, self.format);
+		match pollster::block_on(self.graphics.device.pop_error_scope()) {
+			Some(e) => log::error!("Keeping the previous pipelines, the new shaders failed to compile: {e}"),
+			None => {
+				self.blitter = blitter;
+				self.cursor_pipeline = cursor_pipeline;
+			}
+		}
+		self.backend.reload_shaders(&self.graphics);
+	}
+
+	fn resize(&mut self, width: u32, height: u32) {
+		self.configure_surface(width, height);
+		self.backend.resize(&self.graphics, width, height);
 	}
 
-	pub fn present(&mut self) -> anyhow::Result<()> {
+	/// Render the canvas if given and present it, reporting a `GpuLost` error if the device failed along the way
+	fn frame(&mut self, window: &Window, canvas: Option<&CanvasWidget>, cursor: Option<&BrushCursor>) -> anyhow::Result<()> {
+		let result = match canvas {
+			Some(canvas) => {
+				self.strokes.apply(&canvas.canvas, &mut *self.backend);
+				self.backend.render(&self.graphics, canvas)
+			}
+			None => Ok(()),
+		}
+		.and_then(|()| self.present(window, cursor));
+
+		// What the device reported says more than whatever failed because of it
+		if let Some(error) = self.device_error.lock().unwrap().take() {
+			return Err(GpuLost(error).into());
+		}
+		result
+	}
+
+	fn present(&mut self, window: &Window, cursor: Option<&BrushCursor>) -> anyhow::Result<()> {
 		for _ in 0..3 {
 			let surface_texture = match self.surface.get_current_texture() {
 				Ok(surface_texture) => {
 					if surface_texture.suboptimal {
 						drop(surface_texture);
-						self.handle_window_resize();
+						let size = window.inner_size();
+						self.configure_surface(size.width, size.height);
 						continue;
 					} else {
 						surface_texture
 					}
 				}
-				Err(wgpu::SurfaceError::Outdated) => {
-					self.handle_window_resize();
+				Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+					let size = window.inner_size();
+					self.configure_surface(size.width, size.height);
 					continue;
 				}
+				Err(wgpu::SurfaceError::Timeout) => {
+					log::warn!("Timed out waiting for a frame from the surface, trying again on the next redraw");
+					window.request_redraw();
+					return Ok(());
+				}
 				Err(e) => return Err(anyhow::Error::from(e)),
 			};
 
@@ -101,12 +365,134 @@ impl Ui {
 				base_array_layer: 0,
 				array_layer_count: None,
 			});
-			self.blitter
-				.blit(&self.graphics, self.canvas.get_texture_view(), &surface_texture_view);
+			self.blitter.blit(
+				&self.graphics,
+				self.backend.output().get_texture_view(),
+				&surface_texture_view,
+			);
+			if let Some(cursor) = cursor {
+				self.cursor_pipeline.draw(&self.graphics, cursor, &surface_texture_view);
+			}
 			surface_texture.present();
 			return Ok(());
 		}
 
-		Err(anyhow::format_err!("Failed to render to surface after 3 tries"))
+		Err(GpuLost("Failed to render to surface after 3 tries".to_string()).into())
+	}
+}
+
+/// Rasterizes the canvas on the CPU and copies it to the window with softbuffer, for machines without a usable
+/// graphics adapter
+#[cfg(feature = "backend-raster")]
+struct SoftwarePresenter {
+	context: softbuffer::GraphicsContext,
+	canvas: canvas::RasterCanvas,
+	/// Passes the changes to the strokes on to the canvas
+	strokes: StrokeSync,
+	/// The canvas with the cursor on top, in the 0RGB format softbuffer takes
+	frame: Vec<u32>,
+}
+
+#[cfg(feature = "backend-raster")]
+impl SoftwarePresenter {
+	fn new(window: &Window, width: u32, height: u32) -> anyhow::Result<Self> {
+		let context = unsafe { softbuffer::GraphicsContext::new(window, window) }
+			.map_err(|e| anyhow::format_err!("Failed to create a software surface: {e}"))?;
+		log::info!("Rendering with the {} backend on the CPU", BackendKind::Raster.name());
+		Ok(Self {
+			context,
+			canvas: canvas::RasterCanvas::new(width, height),
+			strokes: StrokeSync::default(),
+			frame: Vec::new(),
+		})
+	}
+
+	fn resize(&mut self, width: u32, height: u32) {
+		self.canvas.resize(width, height);
+	}
+
+	fn present(&mut self, cursor: Option<&BrushCursor>) -> anyhow::Result<()> {
+		let (width, height) = (self.canvas.get_width(), self.canvas.get_height());
+		self.frame.clear();
+		self.frame.extend(
+			self.canvas
+				.pixels()
+				.chunks_exact(4)
+				.map(|pixel| u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]])),
+		);
+		if let Some(cursor) = cursor {
+			draw_cursor(&mut self.frame, width, cursor);
+		}
+		self.context.set_buffer(&self.frame, width as u16, height as u16);
+		Ok(())
+	}
+}
+
+/// Draw the brush cursor the way `cursor.wgsl` does: a ring in the brush color with a white halo
+#[cfg(feature = "backend-raster")]
+fn draw_cursor(frame: &mut [u32], width: u32, cursor: &BrushCursor) {
+	let height = frame.len() as u32 / width.max(1);
+	let [cx, cy] = cursor.center;
+	let reach = cursor.radius + 3.0;
+	let first_col = (cx - reach).floor().max(0.0) as isize;
+	let last_col = (cx + reach).ceil().min(width as f64 - 1.0) as isize;
+	let first_row = (cy - reach).floor().max(0.0) as isize;
+	let last_row = (cy + reach).ceil().min(height as f64 - 1.0) as isize;
+	let mix = |a: f64, b: f64, t: f64| a + (b - a) * t;
+
+	for row in first_row..=last_row {
+		for col in first_col..=last_col {
+			let (x, y) = (col as f64 + 0.5 - cx, row as f64 + 0.5 - cy);
+			let offset = ((x * x + y * y).sqrt() - cursor.radius).abs();
+			let ring = (1.25 - offset).clamp(0.0, 1.0);
+			let halo = (2.5 - offset).clamp(0.0, 1.0) * cursor.color.a as f64 / 255.0;
+			if halo <= 0.0 {
+				continue;
+			}
+
+			let pixel = &mut frame[row as usize * width as usize + col as usize];
+			let [_, r, g, b] = pixel.to_be_bytes();
+			let blended = [(r, cursor.color.r), (g, cursor.color.g), (b, cursor.color.b)]
+				.map(|(below, color)| mix(below as f64, mix(255.0, color as f64, ring), halo).round() as u8);
+			*pixel = u32::from_be_bytes([0, blended[0], blended[1], blended[2]]);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_losing_the_device_is_recovered_from() {
+		let validation = |description: &str| wgpu::Error::Validation {
+			source: Box::new(std::fmt::Error),
+			description: description.to_string(),
+		};
+		assert!(is_device_loss(&validation(
+			"Validation Error\n\nCaused by:\n    Parent device is lost\n"
+		)));
+		assert!(!is_device_loss(&validation(
+			"Validation Error\n\nCaused by:\n    In a set_pipeline command\n    Pipeline is invalid\n"
+		)));
+		assert!(!is_device_loss(&wgpu::Error::OutOfMemory {
+			source: Box::new(std::fmt::Error)
+		}));
+	}
+
+	#[test]
+	fn recoveries_are_limited_within_the_window() {
+		let start = Instant::now();
+		let mut recoveries = Recoveries::default();
+		for i in 0..MAX_RECOVERIES {
+			recoveries.start(start + Duration::from_secs(i as u64)).unwrap();
+		}
+		assert!(recoveries.start(start + RECOVERY_WINDOW - Duration::from_millis(1)).is_err());
+
+		// Refused attempts don't count, and the first rebuild is forgotten once the window moved past it
+		recoveries.start(start + RECOVERY_WINDOW).unwrap();
+		assert!(recoveries.start(start + RECOVERY_WINDOW).is_err());
+		recoveries.start(start + RECOVERY_WINDOW * 2).unwrap();
+		recoveries.start(start + RECOVERY_WINDOW * 2).unwrap();
 	}
 }
@@ -1,27 +1,12 @@
-use std::path::PathBuf;
-
 use linalg::{na::Affine2, *};
 use vello::kurbo::{Affine, Point};
-use wgpu::Device;
-
-pub fn load_wgsl_shader(device: &Device, filename: &str) -> wgpu::ShaderModule {
-	let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-	path.push("src/shaders");
-	path.push(filename);
-	let data = std::fs::read_to_string(&path).expect(&format!("Failed to read shader file at '{}'", path.display()));
-	let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-		label: None,
-		source: wgpu::ShaderSource::Wgsl(data.into()),
-	});
-	shader
-}
 
 macro_rules! timeit {
 	($label:expr, $work:expr) => {{
 		let start = std::time::Instant::now();
 		let result = $work;
 		let dur = start.elapsed();
-		println!("TIME {:?}: {:.2}ms", $label, dur.as_secs_f64() * 1000.0);
+		log::debug!("TIME {:?}: {:.2}ms", $label, dur.as_secs_f64() * 1000.0);
 		result
 	}};
 }
@@ -47,6 +32,12 @@ impl LinToVello<Point> for Vec2 {
 	}
 }
 
+impl LinToVello<Point> for Point2 {
+	fn ltov(self) -> Point {
+		Point::new(self.x, self.y)
+	}
+}
+
 impl VelloToLin<Affine2<f64>> for Affine {
 	fn vtol(self) -> Affine2<f64> {
 		let vals = self.as_coeffs();
@@ -60,3 +51,26 @@ impl LinToVello<Affine> for Affine2<f64> {
 		Affine::new([mat[(0, 0)], mat[(1, 0)], mat[(0, 1)], mat[(1, 1)], mat[(0, 2)], mat[(1, 2)]])
 	}
 }
+
+/// Distance from `p` to the line segment between `a` and `b`
+pub fn segment_distance(p: Point2, a: Point2, b: Point2) -> f64 {
+	let ab = b - a;
+	let len_squared = ab.norm_squared();
+	if len_squared == 0.0 {
+		return (p - a).norm();
+	}
+	let t = ((p - a).dot(&ab) / len_squared).clamp(0.0, 1.0);
+	(p - (a + ab * t)).norm()
+}
+
+/// Check whether a point lies inside a polygon, using the even-odd rule
+pub fn point_in_polygon(p: Point2, polygon: &[Point2]) -> bool {
+	let mut inside = false;
+	for (i, &a) in polygon.iter().enumerate() {
+		let b = polygon[(i + 1) % polygon.len()];
+		if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+			inside = !inside;
+		}
+	}
+	inside
+}